@@ -2,7 +2,7 @@ use std::{collections::HashMap, convert::identity};
 
 use rfr::{
     AbsTimestamp, InstrumentationId, Task, Waker,
-    chunked::{self, RecordData, SeqChunkHeader, SeqId},
+    chunked::{self, ChunkedMetaHeader, RecordData, SeqChunkHeader, SeqId},
 };
 
 /// Data collected for conversion
@@ -11,6 +11,7 @@ use rfr::{
 /// to another format.
 #[derive(Debug)]
 pub(crate) struct CollectedData {
+    pub(crate) meta: ChunkedMetaHeader,
     pub(crate) tasks: HashMap<InstrumentationId, TaskRecords>,
     pub(crate) sequences: HashMap<SeqId, SeqRecords>,
     pub(crate) largest_did: DynamicId,
@@ -75,6 +76,9 @@ pub(crate) struct TaskRecords {
     pub(crate) start: AbsTimestamp,
     pub(crate) end: Option<AbsTimestamp>,
     pub(crate) records: Vec<Record>,
+    /// The number of times that the task was polled on a different sequence (thread) to its
+    /// previous poll.
+    pub(crate) migrations: u64,
 }
 
 impl TaskRecords {
@@ -86,6 +90,7 @@ impl TaskRecords {
             start,
             end: None,
             records: Vec::new(),
+            migrations: 0,
         }
     }
 
@@ -101,6 +106,22 @@ impl TaskRecords {
 
         self.records.push(record);
     }
+
+    /// Count the polls which took place on a different sequence to the previous poll.
+    ///
+    /// The records must already be sorted by timestamp.
+    fn count_migrations(&mut self) {
+        let mut prev_seq_id = None;
+        self.migrations = 0;
+        for record in &self.records {
+            if let Data::TaskPollStart { seq_id, .. } = &record.data {
+                if prev_seq_id.is_some_and(|prev| prev != *seq_id) {
+                    self.migrations += 1;
+                }
+                prev_seq_id = Some(*seq_id);
+            }
+        }
+    }
 }
 
 /// The Dynamic Id is used to provide a compact identifier for sequences, spans, and tasks which
@@ -146,7 +167,13 @@ pub(crate) enum Data {
     TaskDrop { iid: InstrumentationId },
 
     /// A task poll started
-    TaskPollStart { iid: InstrumentationId },
+    ///
+    /// The sequence is the one the poll was recorded on, which identifies the thread that polled
+    /// the task.
+    TaskPollStart {
+        iid: InstrumentationId,
+        seq_id: SeqId,
+    },
     /// A task poll ended
     TaskPollEnd { iid: InstrumentationId },
 
@@ -222,6 +249,23 @@ pub(crate) fn collect_tasks(
         .map_err(|e| format!("failed to open recording: {e:?}"))?;
     recording.load_all_chunks();
 
+    match recording.status() {
+        chunked::RecordingStatus::Finished { .. } => {}
+        chunked::RecordingStatus::FinishedWithErrors { write_errors, .. } => {
+            eprintln!(
+                "warning: {write_errors} chunk writes failed, the recording may be incomplete"
+            );
+        }
+        chunked::RecordingStatus::Unfinished { latest_timestamp } => match latest_timestamp {
+            Some(ts) => eprintln!(
+                "warning: the recording ended abruptly at {secs}.{micros:06}s since the epoch",
+                secs = ts.secs,
+                micros = ts.subsec_micros(),
+            ),
+            None => eprintln!("warning: the recording ended abruptly"),
+        },
+    }
+
     let earliest_timestamp = recording
         .chunks_lossy()
         .find_map(identity)
@@ -250,6 +294,27 @@ pub(crate) fn collect_tasks(
         }
     }
 
+    // Tasks which records refer to, but whose objects weren't available when the records were
+    // written, are shown as unknown tasks so that their records (e.g. wakes) aren't lost.
+    for chunk in recording.chunks_lossy() {
+        let Some(chunk) = chunk else { continue };
+        for seq_chunk in chunk.seq_chunks() {
+            if seq_chunk.unresolved_iids.is_empty() {
+                continue;
+            }
+            for record in &seq_chunk.records {
+                for iid in referenced_task_iids(&record.data) {
+                    if seq_chunk.unresolved_iids.contains(&iid) {
+                        tasks.entry(iid).or_insert_with(|| {
+                            dyn_id.inc();
+                            TaskRecords::new(Task::unknown(iid), dyn_id, earliest_timestamp.clone())
+                        });
+                    }
+                }
+            }
+        }
+    }
+
     enum AddTo {
         Task(InstrumentationId),
         Sequence,
@@ -274,9 +339,13 @@ pub(crate) fn collect_tasks(
                 // Convert RecordData to one or more (task_iid, Data) pairs
                 let records_to_add: Vec<(AddTo, Data)> = match &record.data {
                     RecordData::TaskNew { iid } => vec![(iid.into(), Data::TaskNew { iid: *iid })],
-                    RecordData::TaskPollStart { iid } => {
-                        vec![(iid.into(), Data::TaskPollStart { iid: *iid })]
-                    }
+                    RecordData::TaskPollStart { iid } => vec![(
+                        iid.into(),
+                        Data::TaskPollStart {
+                            iid: *iid,
+                            seq_id: seq_chunk.header.seq_id,
+                        },
+                    )],
                     RecordData::TaskPollEnd { iid } => {
                         vec![(iid.into(), Data::TaskPollEnd { iid: *iid })]
                     }
@@ -403,11 +472,32 @@ pub(crate) fn collect_tasks(
 
     for task_records in tasks.values_mut() {
         task_records.records.sort_by_key(|r| r.timestamp.clone());
+        task_records.count_migrations();
     }
 
     Ok(CollectedData {
+        meta: recording.meta().header.clone(),
         tasks,
         sequences,
         largest_did: dyn_id,
     })
 }
+
+/// The iids of the tasks referred to by a record which will be added to task timelines.
+fn referenced_task_iids(data: &RecordData) -> Vec<InstrumentationId> {
+    match data {
+        RecordData::TaskNew { iid }
+        | RecordData::TaskPollStart { iid }
+        | RecordData::TaskPollEnd { iid }
+        | RecordData::TaskDrop { iid } => vec![*iid],
+        RecordData::WakerWake { waker }
+        | RecordData::WakerWakeByRef { waker }
+        | RecordData::WakerClone { waker }
+        | RecordData::WakerDrop { waker } => {
+            let mut iids = vec![waker.task_iid];
+            iids.extend(waker.context);
+            iids
+        }
+        _ => Vec::new(),
+    }
+}
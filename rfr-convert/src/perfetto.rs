@@ -1,7 +1,7 @@
-use std::{fmt, fs, io::Write, mem};
+use std::{collections::HashMap, fmt, fs, io::Write, mem};
 
 use prost::Message;
-use rfr::{AbsTimestamp, TaskKind, Waker};
+use rfr::{AbsTimestamp, FieldValue, TaskKind, Waker, chunked::SeqId};
 
 use crate::{
     collect::{CollectedData, Data, DynamicId, SeqRecords, TaskRecords, WakeId, WakerAction},
@@ -20,9 +20,9 @@ const SEQ_INCREMENTAL_STATE_CLEARED: u32 = 1;
 // Fixed UUIDs
 const PROCESS_TRACK_UUID: u64 = 1;
 
-fn abs_timestamp_to_nanos(secs: u64, subsec_micros: u32) -> u64 {
+fn abs_timestamp_to_nanos(secs: u64, subsec_nanos: u32) -> u64 {
     secs.saturating_mul(1_000_000_000)
-        .saturating_add(subsec_micros as u64 * 1_000)
+        .saturating_add(subsec_nanos as u64)
 }
 
 fn task_track_uuid(task_records: &TaskRecords) -> u64 {
@@ -31,10 +31,13 @@ fn task_track_uuid(task_records: &TaskRecords) -> u64 {
 
 fn task_track_name(task_records: &TaskRecords) -> String {
     let task = &task_records.task;
-    match task.task_kind {
-        TaskKind::BlockOn => "block_on".to_string(),
-        TaskKind::Blocking if task.task_name.is_empty() => "Blocking".to_string(),
-        _ => task.task_name.clone(),
+    match (&task.task_kind, &task.location) {
+        (TaskKind::BlockOn, _) => "block_on".to_string(),
+        _ if !task.task_name.is_empty() => task.task_name.clone(),
+        // Unnamed tasks are labelled by where they were spawned.
+        (_, Some(location)) => location.to_string(),
+        (TaskKind::Blocking, None) => "Blocking".to_string(),
+        (_, None) => String::new(),
     }
 }
 
@@ -42,8 +45,41 @@ fn sequence_track_uuid(seq_records: &SeqRecords) -> u64 {
     seq_records.header.seq_id.as_u64() + 1_000_000
 }
 
-fn sequence_track_name(seq_records: &SeqRecords) -> String {
-    format!("Sequence {}", seq_records.header.seq_id.as_u64())
+/// Name the sequence tracks after the threads they were recorded on.
+///
+/// Threads which share a name (e.g. Tokio's worker threads) are numbered in the order they were
+/// first seen, e.g. `tokio-runtime-worker-3`.
+fn sequence_track_names(sequences: &[&SeqRecords]) -> HashMap<SeqId, String> {
+    let mut threads_by_name: HashMap<&str, Vec<&SeqRecords>> = HashMap::new();
+    for seq_records in sequences {
+        if let Some(name) = &seq_records.header.thread.name {
+            threads_by_name.entry(name).or_default().push(seq_records);
+        }
+    }
+
+    let mut names = HashMap::new();
+    for (name, mut threads) in threads_by_name {
+        if let [seq_records] = threads.as_slice() {
+            names.insert(seq_records.header.seq_id, name.to_string());
+            continue;
+        }
+        threads.sort_by_key(|seq_records| seq_records.header.thread.first_seen.clone());
+        for (index, seq_records) in threads.into_iter().enumerate() {
+            names.insert(seq_records.header.seq_id, format!("{name}-{}", index + 1));
+        }
+    }
+
+    for seq_records in sequences {
+        let header = &seq_records.header;
+        names
+            .entry(header.seq_id)
+            .or_insert_with(|| match header.thread.os_thread_id {
+                Some(os_thread_id) => format!("Thread {os_thread_id}"),
+                None => format!("Sequence {}", header.seq_id.as_u64()),
+            });
+    }
+
+    names
 }
 
 fn annotation(name: String, value: String) -> DebugAnnotation {
@@ -69,6 +105,37 @@ fn waker_debug_annotations(waker: &Waker) -> Vec<DebugAnnotation> {
     }
 }
 
+fn task_debug_annotations(task_records: &TaskRecords) -> Vec<DebugAnnotation> {
+    let task = &task_records.task;
+    let mut annotations = vec![
+        annotation("task_kind".to_string(), format!("{:?}", task.task_kind)),
+        annotation("task_name".to_string(), task.task_name.clone()),
+        annotation("task_id".to_string(), task.task_id.as_u64().to_string()),
+        annotation("context".to_string(), format!("{:?}", task.context)),
+        annotation(
+            "migrations".to_string(),
+            task_records.migrations.to_string(),
+        ),
+    ];
+    if let Some(location) = &task.location {
+        annotations.push(annotation("location".to_string(), location.to_string()));
+    }
+    for field in &task.fields {
+        let value = match &field.value {
+            FieldValue::F64(value) => value.to_string(),
+            FieldValue::I64(value) => value.to_string(),
+            FieldValue::U64(value) => value.to_string(),
+            FieldValue::I128(value) => value.to_string(),
+            FieldValue::U128(value) => value.to_string(),
+            FieldValue::Bool(value) => value.to_string(),
+            FieldValue::Str(value) => value.clone(),
+        };
+        annotations.push(annotation(field.name.0.clone(), value));
+    }
+
+    annotations
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum TaskState {
     Unknown,
@@ -142,7 +209,7 @@ struct PacketAdder<'a> {
 
 impl<'a> PacketAdder<'a> {
     fn new(packets: &'a mut Vec<TracePacket>, track_uuid: u64, timestamp: AbsTimestamp) -> Self {
-        let timestamp_nanos = abs_timestamp_to_nanos(timestamp.secs, timestamp.subsec_micros);
+        let timestamp_nanos = abs_timestamp_to_nanos(timestamp.secs, timestamp.subsec_nanos);
         Self {
             packets,
             timestamp_nanos,
@@ -252,17 +319,33 @@ pub(crate) fn write_perfetto(
     let mut packets = Vec::new();
 
     // Process track descriptor
+    let meta = &collected_data.meta;
+    let process_name = meta
+        .process
+        .executable_name()
+        .unwrap_or("rfr recording")
+        .to_string();
+    let process_track_name = match &meta.process.hostname {
+        Some(hostname) => format!("{process_name} ({hostname})"),
+        None => process_name.clone(),
+    };
     packets.push(TracePacket {
         trusted_packet_sequence_id: Some(1),
         sequence_flags: Some(SEQ_INCREMENTAL_STATE_CLEARED),
         track_descriptor: Some(TrackDescriptor {
             uuid: Some(PROCESS_TRACK_UUID),
             static_or_dynamic_name: Some(track_descriptor::StaticOrDynamicName::Name(
-                "rfr recording".to_string(),
+                process_track_name,
             )),
             process: Some(ProcessDescriptor {
-                pid: Some(1),
-                process_name: Some("rfr recording".to_string()),
+                pid: Some(i32::try_from(meta.process.pid).unwrap_or(1)),
+                cmdline: meta.process.args.clone(),
+                process_name: Some(process_name),
+                process_labels: meta
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect(),
                 ..Default::default()
             }),
             ..Default::default()
@@ -270,13 +353,18 @@ pub(crate) fn write_perfetto(
         ..Default::default()
     });
 
-    for seq_records in collected_data.sequences() {
+    let sequences = collected_data.sequences();
+    let sequence_names = sequence_track_names(&sequences);
+    for seq_records in sequences {
         if seq_records.records.is_empty() {
             continue;
         }
 
         let track_uuid = sequence_track_uuid(seq_records);
-        let track_name = sequence_track_name(seq_records);
+        let track_name = sequence_names
+            .get(&seq_records.header.seq_id)
+            .cloned()
+            .unwrap_or_default();
 
         // Track descriptor for this sequence
         packets.push(TracePacket {
@@ -388,12 +476,13 @@ pub(crate) fn write_perfetto(
         let mut state = TaskState::Unknown;
         let task = &task_records.task;
         let task_did = task_records.did;
+        let mut prev_poll_seq_id = None;
 
         for record in &task_records.records {
             let mut adder = PacketAdder::new(&mut packets, track_uuid, record.timestamp.clone());
 
             match &record.data {
-                Data::TaskPollStart { .. } => {
+                Data::TaskPollStart { seq_id, .. } => {
                     let flow_id = if let TaskState::IdleScheduled { wake_flow_id } = state {
                         adder
                             .event_type(track_event::Type::SliceEnd)
@@ -415,11 +504,22 @@ pub(crate) fn write_perfetto(
                     }
                     .to_string();
 
+                    let thread_name = sequence_names
+                        .get(seq_id)
+                        .cloned()
+                        .unwrap_or_else(|| format!("Sequence {}", seq_id.as_u64()));
+                    let migrated = prev_poll_seq_id.is_some_and(|prev| prev != *seq_id);
+                    prev_poll_seq_id = Some(*seq_id);
+
                     adder
                         .event_type(track_event::Type::SliceBegin)
                         .name(active_name.clone())
                         .categories(vec![active_name, format!("iid={}", task.iid.as_u64())])
                         .terminating_flow_id(flow_id)
+                        .debug_annotations(vec![
+                            annotation("thread".to_string(), thread_name),
+                            annotation("migrated".to_string(), migrated.to_string()),
+                        ])
                         .add_and_clear();
                 }
                 Data::TaskPollEnd { .. } => {
@@ -461,12 +561,7 @@ pub(crate) fn write_perfetto(
                             format!("iid={}", task.iid.as_u64()),
                         ])
                         .terminating_flow_id(Some(FlowId::spawn(task_did)))
-                        .debug_annotations(vec![
-                            annotation("task_kind".to_string(), format!("{:?}", task.task_kind)),
-                            annotation("task_name".to_string(), task.task_name.clone()),
-                            annotation("task_id".to_string(), task.task_id.as_u64().to_string()),
-                            annotation("context".to_string(), format!("{:?}", task.context)),
-                        ])
+                        .debug_annotations(task_debug_annotations(task_records))
                         .add_and_clear();
                 }
                 Data::TaskDrop { .. } => {
@@ -1,4 +1,9 @@
 mod subscriber;
 
-pub use subscriber::RfrChunkedLayer;
 pub use subscriber::RfrLayer;
+pub use subscriber::{
+    BuildError, CompletionGuard, DumpError, Dumper, LayerStats, RfrChunkedLayer,
+    RfrChunkedLayerBuilder, StatsHandle, Trigger, TriggerConfig,
+};
+
+pub use rfr::chunked::{DirectoryLayout, Retention, WriterStats};
@@ -1,29 +1,93 @@
 use std::{
+    cell::RefCell,
     collections::HashMap,
-    error, fmt,
-    sync::{Arc, Mutex},
+    error, fmt, io,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{self, AtomicBool, AtomicU64},
+    },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use tracing::{Event, Metadata, Subscriber, span, subscriber::Interest};
-use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+use tracing::{Event, Metadata, Subscriber, callsite, span, subscriber::Interest};
+use tracing_subscriber::{
+    Layer,
+    layer::Context,
+    registry::{Extensions, LookupSpan},
+};
 
 use rfr::{
-    AbsTimestamp, Callsite, CallsiteId, InstrumentationId,
-    chunked::{self, ChunkedWriter},
+    AbsTimestamp, Callsite, InstrumentationId,
+    chunked::{self, ChunkedWriter, ChunkedWriterBuilder, DirectoryLayout, Retention, WriterStats},
 };
 
-use crate::subscriber::common::{
-    EventKind, SpanKind, SpawnFields, SpawnSpan, TaskId, TaskKind, TraceKind, WakerFields, WakerOp,
-    get_context_task_iid, to_callsite, to_callsite_id, to_iid,
+use crate::subscriber::{
+    common::{
+        AsyncOpFields, AsyncOpPollSpan, AsyncOpSpan, EventKind, FieldValues, GenericSpan,
+        PollOpFields, ResourceFields, ResourceSpan, SpanKind, SpawnFields, SpawnSpan,
+        StateUpdateFields, TaskId, TaskIids, TaskKind, TraceKind, WakerFields, WakerOp, WakerTask,
+        assign_iid, find_iid_in_scope, get_context_task_iid, get_parent, to_callsite, to_iid,
+    },
+    trigger::{TriggerConfig, Triggers},
 };
 
+thread_local! {
+    /// The tasks which are currently being polled on this thread, the innermost task is last.
+    ///
+    /// Resource spans are entered when an async op is created, so the current span's scope
+    /// doesn't necessarily lead back to the task which is being polled.
+    static POLLING_TASKS: RefCell<Vec<InstrumentationId>> = const { RefCell::new(Vec::new()) };
+}
+
+fn polling_task_iid() -> Option<InstrumentationId> {
+    POLLING_TASKS.with_borrow(|tasks| tasks.last().copied())
+}
+
 struct WriterHandle {
     writer: Arc<ChunkedWriter>,
     join_handle: Mutex<Option<JoinHandle<()>>>,
 }
 
+impl WriterHandle {
+    fn complete(&self) {
+        let join_handle = {
+            let mut guard = self.join_handle.lock().expect("poisoned");
+            guard.take()
+        };
+        let Some(join_handle) = join_handle else {
+            // Some other thread has already completed the recording.
+            return;
+        };
+
+        // Closing the writer wakes the writer thread, which then exits its loop.
+        self.writer.close();
+        if join_handle.join().is_err() {
+            eprintln!("The RFR writer thread panicked. Recording may be incomplete");
+        }
+
+        if let Err(err) = self.writer.finish() {
+            eprintln!("Failed to finish recording. Recording may be incomplete: {err}");
+        }
+    }
+}
+
+/// A guard which completes the recording when it is dropped.
+///
+/// Created with [`RfrChunkedLayer::completion_guard`]. This is useful when the layer itself is
+/// never dropped, which is the case when it is part of the global default subscriber.
+#[must_use = "the recording is completed when the guard is dropped"]
+pub struct CompletionGuard {
+    writer_handle: Arc<WriterHandle>,
+}
+
+impl Drop for CompletionGuard {
+    fn drop(&mut self) {
+        self.writer_handle.complete();
+    }
+}
+
 pub struct Flusher {
     writer: Arc<ChunkedWriter>,
 }
@@ -57,36 +121,467 @@ impl fmt::Display for FlushError {
 
 impl error::Error for FlushError {}
 
-pub struct RfrChunkedLayer {
-    writer_handle: WriterHandle,
-    callsite_cache: Mutex<HashMap<CallsiteId, (Callsite, TraceKind)>>,
-    object_cache: Mutex<HashMap<InstrumentationId, chunked::Object>>,
+/// A handle to dump an in-memory recording to disk.
+///
+/// Created with [`RfrChunkedLayer::dumper`].
+pub struct Dumper {
+    writer: Arc<ChunkedWriter>,
 }
 
-impl RfrChunkedLayer {
-    pub fn new(base_dir: &str) -> Self {
-        let writer_handle = Self::spawn_writer(base_dir.to_owned());
+impl Dumper {
+    /// Dumps the chunks currently held in memory to a new chunked recording at `path`.
+    ///
+    /// The recording will contain the chunks covering the last ring duration (see
+    /// [`RfrChunkedLayerBuilder::build_in_memory`]) up until the moment this method is called. Recording
+    /// continues in memory afterwards, so this method can be called multiple times with
+    /// different paths.
+    pub fn dump_to(&self, path: impl AsRef<Path>) -> Result<(), DumpError> {
+        self.writer
+            .dump_to(path)
+            .map_err(|inner| DumpError { inner })
+    }
+}
+
+/// Error dumping an in-memory recording to disk
+#[derive(Debug)]
+pub struct DumpError {
+    inner: chunked::DumpError,
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
 
+impl error::Error for DumpError {}
+
+/// A handle to read the statistics of an [`RfrChunkedLayer`].
+///
+/// Created with [`RfrChunkedLayer::stats_handle`], it can be kept after the layer has been moved
+/// into the subscriber.
+pub struct StatsHandle {
+    writer: Arc<ChunkedWriter>,
+    health: Arc<WriteHealth>,
+}
+
+impl StatsHandle {
+    /// Returns a snapshot of the layer's statistics.
+    pub fn stats(&self) -> LayerStats {
+        LayerStats {
+            writer: self.writer.stats(),
+            write_failures: self.health.failure_count(),
+            records_discarded_while_paused: self.health.discarded_count(),
+        }
+    }
+}
+
+/// A snapshot of the statistics of an [`RfrChunkedLayer`].
+#[derive(Debug, Clone)]
+pub struct LayerStats {
+    /// The statistics of the underlying chunked writer.
+    pub writer: WriterStats,
+    /// The number of failures writing the recording.
+    ///
+    /// Each failure pauses recording for a while, see
+    /// [`RfrChunkedLayerBuilder::write_error_pause`].
+    pub write_failures: u64,
+    /// The number of records which were discarded because recording was paused.
+    pub records_discarded_while_paused: u64,
+}
+
+/// Error building an [`RfrChunkedLayer`]
+#[derive(Debug)]
+pub struct BuildError {
+    inner: BuildErrorKind,
+}
+
+#[derive(Debug)]
+enum BuildErrorKind {
+    NewWriter(chunked::NewChunkedWriterError),
+    SpawnWriterThread(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            BuildErrorKind::NewWriter(inner) => write!(f, "{inner}"),
+            BuildErrorKind::SpawnWriterThread(inner) => {
+                write!(f, "failed to spawn writer thread: {inner}")
+            }
+        }
+    }
+}
+
+impl From<chunked::NewChunkedWriterError> for BuildError {
+    fn from(value: chunked::NewChunkedWriterError) -> Self {
         Self {
-            writer_handle,
-            callsite_cache: Default::default(),
-            object_cache: Default::default(),
+            inner: BuildErrorKind::NewWriter(value),
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+/// Tracks failures to write the recording.
+///
+/// After a failure, recording is paused for a while instead of failing repeatedly (e.g. while the
+/// disk is full). Failures are reported when recording is paused, not every time they occur.
+#[derive(Debug)]
+struct WriteHealth {
+    failure_count: AtomicU64,
+    discarded_count: AtomicU64,
+    pause_duration: Duration,
+    is_paused: AtomicBool,
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl WriteHealth {
+    fn new(pause_duration: Duration) -> Self {
+        Self {
+            failure_count: AtomicU64::new(0),
+            discarded_count: AtomicU64::new(0),
+            pause_duration,
+            is_paused: AtomicBool::new(false),
+            paused_until: Mutex::new(None),
         }
     }
 
-    fn spawn_writer(base_dir: String) -> WriterHandle {
-        let writer = Arc::new(ChunkedWriter::try_new(base_dir).unwrap());
+    fn record_failure(&self, err: &dyn error::Error) {
+        self.failure_count.fetch_add(1, atomic::Ordering::Relaxed);
+
+        let mut paused_until = self.paused_until.lock().expect("paused until poisoned");
+        if paused_until.is_none() {
+            eprintln!(
+                "Failed to write recording, pausing recording for {pause:?}: {err}",
+                pause = self.pause_duration,
+            );
+        }
+        *paused_until = Some(Instant::now() + self.pause_duration);
+        self.is_paused.store(true, atomic::Ordering::Release);
+    }
+
+    fn is_paused(&self) -> bool {
+        if !self.is_paused.load(atomic::Ordering::Acquire) {
+            return false;
+        }
+
+        let mut paused_until = self.paused_until.lock().expect("paused until poisoned");
+        match *paused_until {
+            Some(until) if Instant::now() < until => true,
+            _ => {
+                *paused_until = None;
+                self.is_paused.store(false, atomic::Ordering::Release);
+                false
+            }
+        }
+    }
+
+    fn record_discarded(&self) {
+        self.discarded_count.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    fn failure_count(&self) -> u64 {
+        self.failure_count.load(atomic::Ordering::Relaxed)
+    }
+
+    fn discarded_count(&self) -> u64 {
+        self.discarded_count.load(atomic::Ordering::Relaxed)
+    }
+}
+
+/// The categories of instrumentation which are recorded.
+#[derive(Debug, Clone, Copy)]
+struct Categories {
+    tasks: bool,
+    wakers: bool,
+    spans: bool,
+    events: bool,
+}
+
+impl Default for Categories {
+    fn default() -> Self {
+        Self {
+            tasks: true,
+            wakers: true,
+            spans: true,
+            events: true,
+        }
+    }
+}
+
+impl Categories {
+    fn includes(&self, data: &chunked::RecordData) -> bool {
+        use chunked::RecordData;
+
+        match data {
+            RecordData::TaskNew { .. }
+            | RecordData::TaskPollStart { .. }
+            | RecordData::TaskPollEnd { .. }
+            | RecordData::TaskDrop { .. } => self.tasks,
+            RecordData::WakerWake { .. }
+            | RecordData::WakerWakeByRef { .. }
+            | RecordData::WakerClone { .. }
+            | RecordData::WakerDrop { .. } => self.wakers,
+            RecordData::SpanNew { .. }
+            | RecordData::SpanEnter { .. }
+            | RecordData::SpanExit { .. }
+            | RecordData::SpanClose { .. } => self.spans,
+            RecordData::Event { .. } => self.events,
+            _ => true,
+        }
+    }
+
+    /// Whether any records are written for instrumentation of this kind.
+    fn includes_kind(&self, kind: &TraceKind) -> bool {
+        match kind {
+            TraceKind::Span(SpanKind::Spawn) => self.tasks,
+            TraceKind::Event(EventKind::Waker) => self.wakers,
+            TraceKind::Span(SpanKind::Generic) => self.spans,
+            TraceKind::Event(EventKind::Generic) => self.events,
+            _ => true,
+        }
+    }
+
+    /// Whether the records for a span with these extensions are written.
+    fn includes_span(&self, extensions: &Extensions<'_>) -> bool {
+        if extensions.get::<TaskId>().is_some() {
+            self.tasks
+        } else if extensions.get::<GenericSpan>().is_some() {
+            self.spans
+        } else {
+            true
+        }
+    }
+}
+
+/// A builder for an [`RfrChunkedLayer`].
+///
+/// Created with [`RfrChunkedLayer::builder`]. The defaults match [`RfrChunkedLayer::new`].
+#[derive(Debug, Clone)]
+pub struct RfrChunkedLayerBuilder {
+    writer_builder: ChunkedWriterBuilder,
+    thread_name: String,
+    categories: Categories,
+    write_error_pause: Duration,
+}
+
+impl RfrChunkedLayerBuilder {
+    fn new() -> Self {
+        Self {
+            writer_builder: ChunkedWriter::builder(),
+            thread_name: "rfr-writer".to_owned(),
+            categories: Categories::default(),
+            write_error_pause: Duration::from_secs(10),
+        }
+    }
+
+    /// Set the length of execution time that each chunk covers.
+    ///
+    /// See [`ChunkedWriterBuilder::chunk_period`] for the valid values. The default is 1 second.
+    pub fn chunk_period(mut self, chunk_period: Duration) -> Self {
+        self.writer_builder = self.writer_builder.chunk_period(chunk_period);
+        self
+    }
+
+    /// Set how long after the end of a chunk's interval to wait before writing it.
+    ///
+    /// The default is 150 milliseconds.
+    pub fn write_delay(mut self, write_delay: Duration) -> Self {
+        self.writer_builder = self.writer_builder.write_delay(write_delay);
+        self
+    }
+
+    /// Set how chunk files are arranged within the recording directory.
+    pub fn directory_layout(mut self, layout: DirectoryLayout) -> Self {
+        self.writer_builder = self.writer_builder.directory_layout(layout);
+        self
+    }
+
+    /// Set the limits on how much of the recording is kept on disk.
+    ///
+    /// This allows the layer to be used as an always-on flight recorder with bounded disk usage.
+    /// It only applies to layers created with [`build`].
+    ///
+    /// [`build`]: fn@Self::build
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.writer_builder = self.writer_builder.retention(retention);
+        self
+    }
+
+    /// Set how often the chunks still being recorded to are checkpointed to disk.
+    ///
+    /// If the process is killed (e.g. by the OOM killer), at most this much of the recording is
+    /// lost. By default, no checkpoints are written. It only applies to layers created with
+    /// [`build`].
+    ///
+    /// [`build`]: fn@Self::build
+    pub fn checkpoint_interval(mut self, checkpoint_interval: Duration) -> Self {
+        self.writer_builder = self.writer_builder.checkpoint_interval(checkpoint_interval);
+        self
+    }
+
+    /// Set the version of Tokio being recorded, which is stored in the recording's metadata.
+    ///
+    /// The layer doesn't depend on Tokio, so it can't determine the version itself.
+    pub fn tokio_version(mut self, tokio_version: impl Into<String>) -> Self {
+        self.writer_builder = self.writer_builder.tokio_version(tokio_version);
+        self
+    }
+
+    /// Set the number of runtime worker threads, which is stored in the recording's metadata.
+    ///
+    /// The layer is usually created before the runtime, so it can't determine this itself.
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.writer_builder = self.writer_builder.worker_threads(worker_threads);
+        self
+    }
+
+    /// Add a key/value label to the recording's metadata, e.g. the service or deployment name.
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.writer_builder = self.writer_builder.label(key, value);
+        self
+    }
+
+    /// Set the name of the thread which writes the recording. The default is `rfr-writer`.
+    pub fn writer_thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// Set how long to pause recording for after the recording fails to be written.
+    ///
+    /// Failures (e.g. because the disk is full) don't crash the instrumented application, instead
+    /// records are discarded until the pause is over. The default is 10 seconds.
+    pub fn write_error_pause(mut self, pause: Duration) -> Self {
+        self.write_error_pause = pause;
+        self
+    }
+
+    /// Set whether tasks are recorded (new, poll start and end, and drop).
+    pub fn record_tasks(mut self, enabled: bool) -> Self {
+        self.categories.tasks = enabled;
+        self
+    }
+
+    /// Set whether waker operations are recorded.
+    pub fn record_wakers(mut self, enabled: bool) -> Self {
+        self.categories.wakers = enabled;
+        self
+    }
+
+    /// Set whether spans which aren't part of the runtime instrumentation are recorded.
+    pub fn record_spans(mut self, enabled: bool) -> Self {
+        self.categories.spans = enabled;
+        self
+    }
+
+    /// Set whether events which aren't part of the runtime instrumentation are recorded.
+    pub fn record_events(mut self, enabled: bool) -> Self {
+        self.categories.events = enabled;
+        self
+    }
+
+    /// Build a layer which writes a new chunked recording at `base_dir`.
+    pub fn build(self, base_dir: impl AsRef<Path>) -> Result<RfrChunkedLayer, BuildError> {
+        let writer = self
+            .writer_builder
+            .clone()
+            .build(base_dir)
+            .map_err(BuildError::from)?;
+        self.into_layer(writer, None)
+    }
+
+    /// Build a layer which keeps the recording in memory.
+    ///
+    /// Only the chunks covering the last `ring_duration` of execution time are kept, nothing is
+    /// written to disk until the recording is dumped with a [`Dumper`] (see
+    /// [`RfrChunkedLayer::dumper`]).
+    pub fn build_in_memory(self, ring_duration: Duration) -> Result<RfrChunkedLayer, BuildError> {
+        let writer = self
+            .writer_builder
+            .clone()
+            .build_in_memory(ring_duration)
+            .map_err(BuildError::from)?;
+        self.into_layer(writer, None)
+    }
+
+    /// Build a layer which captures the recording automatically when a trigger fires.
+    ///
+    /// The recording is kept in memory (see [`build_in_memory`]). When one of the configured
+    /// triggers fires, the window around the trigger is written to a new chunked recording in
+    /// the captures directory.
+    ///
+    /// [`build_in_memory`]: fn@Self::build_in_memory
+    pub fn build_with_triggers(self, config: TriggerConfig) -> Result<RfrChunkedLayer, BuildError> {
+        let writer = self
+            .writer_builder
+            .clone()
+            .build_in_memory(config.before + config.after)
+            .map_err(BuildError::from)?;
+        self.into_layer(writer, Some(Arc::new(Triggers::new(config))))
+    }
+
+    fn into_layer(
+        self,
+        writer: ChunkedWriter,
+        triggers: Option<Arc<Triggers>>,
+    ) -> Result<RfrChunkedLayer, BuildError> {
+        let writer = Arc::new(writer);
+        let health = Arc::new(WriteHealth::new(self.write_error_pause));
 
         let thread_writer = Arc::clone(&writer);
+        let thread_triggers = triggers.clone();
+        let thread_health = Arc::clone(&health);
         let join_handle = thread::Builder::new()
-            .name("rfr-writer".to_owned())
-            .spawn(move || run_writer_loop(thread_writer))
-            .unwrap();
+            .name(self.thread_name)
+            .spawn(move || run_writer_loop(thread_writer, thread_triggers, thread_health))
+            .map_err(|err| BuildError {
+                inner: BuildErrorKind::SpawnWriterThread(err),
+            })?;
 
-        WriterHandle {
-            writer,
-            join_handle: Mutex::new(Some(join_handle)),
-        }
+        Ok(RfrChunkedLayer {
+            writer_handle: Arc::new(WriterHandle {
+                writer,
+                join_handle: Mutex::new(Some(join_handle)),
+            }),
+            callsite_cache: Default::default(),
+            object_cache: Default::default(),
+            task_iids: Default::default(),
+            triggers,
+            categories: self.categories,
+            health,
+        })
+    }
+}
+
+pub struct RfrChunkedLayer {
+    writer_handle: Arc<WriterHandle>,
+    callsite_cache: Mutex<HashMap<callsite::Identifier, (Callsite, TraceKind)>>,
+    object_cache: Mutex<HashMap<InstrumentationId, chunked::Object>>,
+    task_iids: TaskIids,
+    triggers: Option<Arc<Triggers>>,
+    categories: Categories,
+    health: Arc<WriteHealth>,
+}
+
+impl RfrChunkedLayer {
+    /// Creates a new layer which writes a chunked recording at `base_dir`.
+    ///
+    /// Use [`builder`] to configure the layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunked recording can't be created.
+    ///
+    /// [`builder`]: fn@Self::builder
+    pub fn new(base_dir: &str) -> Self {
+        Self::builder().build(base_dir).unwrap()
+    }
+
+    /// Returns a builder to configure a new layer.
+    pub fn builder() -> RfrChunkedLayerBuilder {
+        RfrChunkedLayerBuilder::new()
     }
 
     pub fn flusher(&self) -> Flusher {
@@ -95,21 +590,50 @@ impl RfrChunkedLayer {
         }
     }
 
-    pub fn complete(&self) {
-        let join_handle = {
-            let mut guard = self.writer_handle.join_handle.lock().expect("poisoned");
-            guard.take()
-        };
-        if let Some(join_handle) = join_handle {
-            // TODO(hds): signal writer thread to stop
-            join_handle.join().unwrap();
+    /// Returns a handle which can dump the recording to disk.
+    ///
+    /// Dumping will only succeed if the layer was created with
+    /// [`RfrChunkedLayerBuilder::build_in_memory`].
+    pub fn dumper(&self) -> Dumper {
+        Dumper {
+            writer: Arc::clone(&self.writer_handle.writer),
+        }
+    }
 
-            self.writer_handle.writer.write_all_chunks();
-        } else {
-            // Otherwise some other thread has joined on the writer.
+    /// Returns a snapshot of the layer's statistics.
+    pub fn stats(&self) -> LayerStats {
+        self.stats_handle().stats()
+    }
+
+    /// Returns a handle which can read the layer's statistics.
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            writer: Arc::clone(&self.writer_handle.writer),
+            health: Arc::clone(&self.health),
+        }
+    }
+
+    /// Returns a guard which completes the recording when it is dropped.
+    ///
+    /// See [`complete`] for details.
+    ///
+    /// [`complete`]: fn@Self::complete
+    pub fn completion_guard(&self) -> CompletionGuard {
+        CompletionGuard {
+            writer_handle: Arc::clone(&self.writer_handle),
         }
     }
 
+    /// Stops recording and writes out the rest of the recording.
+    ///
+    /// The writer thread is stopped, then all remaining chunks and callsites are written and the
+    /// recording is marked as cleanly finished. Anything recorded afterwards is discarded.
+    ///
+    /// This is also done when the layer is dropped. Only the first call has any effect.
+    pub fn complete(&self) {
+        self.writer_handle.complete();
+    }
+
     fn new_object(&self, iid: InstrumentationId, object: chunked::Object) {
         let mut object_cache = self.object_cache.lock().expect("object cache poisoned");
         object_cache.insert(iid, object);
@@ -127,32 +651,82 @@ impl RfrChunkedLayer {
             .collect()
     }
 
+    /// Whether any spans or events of this kind need to be handled.
+    ///
+    /// Triggers observe task and waker records, even if they aren't written.
+    fn handles_kind(&self, kind: &TraceKind) -> bool {
+        let is_observed = self.triggers.is_some()
+            && matches!(
+                kind,
+                TraceKind::Span(SpanKind::Spawn) | TraceKind::Event(EventKind::Waker)
+            );
+        self.categories.includes_kind(kind) || is_observed
+    }
+
     fn write_record(&self, timestamp: AbsTimestamp, data: chunked::RecordData) {
-        self.writer_handle
-            .writer
-            .with_seq_chunk_buffer(timestamp.clone(), |current_buffer| {
-                let record = chunked::Record {
-                    meta: chunked::Meta {
-                        timestamp: current_buffer.chunk_timestamp(&timestamp),
-                    },
-                    data,
-                };
-                current_buffer.append_record(record, |task_ids| self.get_objects(task_ids));
-            });
+        if let Some(triggers) = &self.triggers {
+            triggers.observe(&timestamp, &data);
+        }
+        self.write_unobserved_record(timestamp, data);
+    }
+
+    /// Write a record without the triggers observing it.
+    fn write_unobserved_record(&self, timestamp: AbsTimestamp, data: chunked::RecordData) {
+        if !self.categories.includes(&data) {
+            return;
+        }
+        if self.health.is_paused() {
+            self.health.record_discarded();
+            return;
+        }
+
+        let result =
+            self.writer_handle
+                .writer
+                .with_seq_chunk_buffer(timestamp.clone(), |current_buffer| {
+                    let record = chunked::Record {
+                        meta: chunked::Meta {
+                            timestamp: current_buffer.chunk_timestamp(&timestamp),
+                        },
+                        data,
+                    };
+                    current_buffer.append_record(record, |task_ids| self.get_objects(task_ids))
+                });
+        if let Err(err) = result {
+            self.health.record_failure(&err);
+        }
     }
 }
 
-fn run_writer_loop(writer: Arc<ChunkedWriter>) {
+fn run_writer_loop(
+    writer: Arc<ChunkedWriter>,
+    triggers: Option<Arc<Triggers>>,
+    health: Arc<WriteHealth>,
+) {
     loop {
         if writer.is_closed() {
             break;
         }
 
-        let Ok(sleep_duration) = writer.write_completed_chunks() else {
-            // Error occurred, break.
-            break;
+        let mut sleep_duration = match writer.write_completed_chunks() {
+            Ok(sleep_duration) => sleep_duration,
+            Err(err) => {
+                health.record_failure(&err);
+                Duration::from_micros(writer.chunk_period_micros() as u64)
+            }
         };
-        thread::sleep(sleep_duration);
+        if let Some(triggers) = &triggers
+            && let Some(capture_due_in) = triggers.run_pending(&writer)
+        {
+            sleep_duration = sleep_duration.min(capture_due_in);
+        }
+        writer.sleep_unless_closed(sleep_duration);
+    }
+}
+
+impl Drop for RfrChunkedLayer {
+    fn drop(&mut self) {
+        self.complete();
     }
 }
 
@@ -163,18 +737,24 @@ where
     fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
         match TraceKind::try_from(metadata) {
             Ok(kind) => {
-                let callsite_id = to_callsite_id(metadata);
+                // Callsites of categories which aren't recorded are still enabled, the interest
+                // applies to all the layers of the subscriber. They are skipped when their spans
+                // and events are recorded instead.
                 let mut callsite_cache = self
                     .callsite_cache
                     .lock()
                     .expect("callsite cache is poisoned");
-                callsite_cache.entry(callsite_id).or_insert_with(|| {
-                    let new_callsite = to_callsite(metadata);
-                    self.writer_handle
-                        .writer
-                        .register_callsite(new_callsite.clone());
-                    (new_callsite, kind)
-                });
+                callsite_cache
+                    .entry(metadata.callsite())
+                    .or_insert_with(|| {
+                        let mut new_callsite = to_callsite(metadata);
+                        // The callsite Id is changed if it collides with another callsite.
+                        new_callsite.callsite_id = self
+                            .writer_handle
+                            .writer
+                            .register_callsite(new_callsite.clone());
+                        (new_callsite, kind)
+                    });
 
                 Interest::always()
             }
@@ -184,14 +764,22 @@ where
 
     fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
         let timestamp = AbsTimestamp::now();
-        let callsite_id = to_callsite_id(attrs.metadata());
-        let kind = {
+        let span = ctx
+            .span(id)
+            .expect("new_span {id:?} not found, this is a bug");
+        // Spans which aren't recorded may still be referenced (e.g. as a parent), so every span
+        // needs an iid.
+        let iid = assign_iid(&span);
+        let (callsite_id, kind) = {
             let callsite_cache = self.callsite_cache.lock().expect("callsite cache poisoned");
-            let Some(callsite_kind) = callsite_cache.get(&callsite_id) else {
+            let Some((callsite, kind)) = callsite_cache.get(&attrs.metadata().callsite()) else {
                 return;
             };
-            callsite_kind.1.clone()
+            (callsite.callsite_id, kind.clone())
         };
+        if !self.handles_kind(&kind) {
+            return;
+        }
         match kind {
             TraceKind::Span(SpanKind::Spawn) => {
                 let mut fields = SpawnFields::default();
@@ -201,15 +789,13 @@ where
                 }
                 let context = get_context_task_iid(&ctx);
 
-                let spawn = SpawnSpan::new(callsite_id, id.clone(), context, fields);
+                let spawn = SpawnSpan::new(callsite_id, iid, context, fields);
 
-                let span = ctx
-                    .span(id)
-                    .expect("new_span {id:?} not found, this is a bug");
                 let mut extensions = span.extensions_mut();
                 if extensions.get_mut::<TaskId>().is_none() {
                     extensions.insert(spawn.task_id);
                 }
+                self.task_iids.insert(id.clone(), spawn.iid);
                 {
                     let task_id = rfr::TaskId::from(spawn.task_id.0);
                     let task = chunked::Object::Task(rfr::Task {
@@ -226,12 +812,86 @@ where
                         },
 
                         context: spawn.context,
+                        location: spawn.location,
+                        fields: spawn.fields,
                     });
                     self.new_object(spawn.iid, task);
                     let rec_data = chunked::RecordData::TaskNew { iid: spawn.iid };
                     self.write_record(timestamp, rec_data);
                 }
             }
+            TraceKind::Span(SpanKind::Resource) => {
+                let mut fields = ResourceFields::default();
+                attrs.record(&mut fields);
+                if !fields.is_valid() {
+                    return;
+                }
+
+                span.extensions_mut().insert(ResourceSpan);
+
+                let resource = chunked::Object::Resource(fields.into_resource(iid, callsite_id));
+                self.new_object(iid, resource);
+                self.write_record(timestamp, chunked::RecordData::ResourceNew { iid });
+            }
+            TraceKind::Span(SpanKind::AsyncOp) => {
+                let mut fields = AsyncOpFields::default();
+                attrs.record(&mut fields);
+                if !fields.is_valid() {
+                    return;
+                }
+
+                // The async op span is created within the scope of its resource span.
+                let Some(resource_span) = span
+                    .parent()
+                    .filter(|parent| parent.extensions().get::<ResourceSpan>().is_some())
+                else {
+                    return;
+                };
+                let Some(resource_iid) = to_iid(&resource_span) else {
+                    return;
+                };
+                span.extensions_mut().insert(AsyncOpSpan);
+
+                let async_op = chunked::Object::AsyncOp(fields.into_async_op(
+                    iid,
+                    callsite_id,
+                    resource_iid,
+                    polling_task_iid(),
+                ));
+                self.new_object(iid, async_op);
+                self.write_record(timestamp, chunked::RecordData::AsyncOpNew { iid });
+            }
+            TraceKind::Span(SpanKind::AsyncOpPoll) => {
+                let Some(async_op_iid) = span
+                    .parent()
+                    .filter(|parent| parent.extensions().get::<AsyncOpSpan>().is_some())
+                    .and_then(|async_op_span| to_iid(&async_op_span))
+                else {
+                    return;
+                };
+                span.extensions_mut()
+                    .insert(AsyncOpPollSpan { async_op_iid });
+            }
+            TraceKind::Span(SpanKind::Generic) => {
+                let mut fields = FieldValues::new(attrs.metadata());
+                attrs.record(&mut fields);
+                let (split_field_values, dynamic_fields) = fields.into_parts();
+
+                let parent = get_parent(attrs.is_root(), span.parent(), |extensions| {
+                    self.categories.includes_span(extensions)
+                });
+                span.extensions_mut().insert(GenericSpan);
+
+                let span_object = chunked::Object::Span(rfr::Span::new(
+                    iid,
+                    callsite_id,
+                    parent,
+                    split_field_values,
+                    dynamic_fields,
+                ));
+                self.new_object(iid, span_object);
+                self.write_record(timestamp, chunked::RecordData::SpanNew { iid });
+            }
             _ => {
                 // Not yet implemented
             }
@@ -240,14 +900,16 @@ where
 
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
         let timestamp = AbsTimestamp::now();
-        let callsite = to_callsite_id(event.metadata());
-        let kind = {
+        let (callsite_id, kind) = {
             let callsite_cache = self.callsite_cache.lock().expect("callsite cache poisoned");
-            let Some(callsite_kind) = callsite_cache.get(&callsite) else {
+            let Some((callsite, kind)) = callsite_cache.get(&event.metadata().callsite()) else {
                 return;
             };
-            callsite_kind.1.clone()
+            (callsite.callsite_id, kind.clone())
         };
+        if !self.handles_kind(&kind) {
+            return;
+        }
         match kind {
             TraceKind::Event(EventKind::Waker) => {
                 let mut fields = WakerFields::default();
@@ -259,9 +921,10 @@ where
                 let task_span_id = fields.task_span_id.unwrap();
 
                 {
+                    let task = self.task_iids.waker_task(&ctx, &task_span_id);
                     let waker = rfr::Waker {
-                        task_iid: to_iid(&task_span_id),
-                        context: ctx.current_span().id().map(to_iid),
+                        task_iid: task.iid(),
+                        context: get_context_task_iid(&ctx),
                     };
                     let waker_data = match op {
                         WakerOp::Wake => chunked::RecordData::WakerWake { waker },
@@ -270,8 +933,76 @@ where
                         WakerOp::Drop => chunked::RecordData::WakerDrop { waker },
                     };
 
-                    self.write_record(timestamp, waker_data);
+                    match task {
+                        WakerTask::Open(_) => self.write_record(timestamp, waker_data),
+                        // A closed task will never be polled, so triggers would wait for it
+                        // forever.
+                        WakerTask::Closed(_) => self.write_unobserved_record(timestamp, waker_data),
+                    }
+                }
+            }
+            TraceKind::Event(EventKind::PollOp) => {
+                let mut fields = PollOpFields::default();
+                event.record(&mut fields);
+                if !fields.is_valid() {
+                    return;
+                }
+
+                let Some(resource_iid) =
+                    find_iid_in_scope::<ResourceSpan, _>(ctx.event_span(event))
+                else {
+                    return;
+                };
+                let async_op_iid = find_iid_in_scope::<AsyncOpSpan, _>(ctx.event_span(event));
+                let poll_op = fields.into_poll_op(resource_iid, async_op_iid, polling_task_iid());
+                self.write_record(timestamp, chunked::RecordData::PollOp { poll_op });
+            }
+            TraceKind::Event(EventKind::ResourceStateUpdate) => {
+                let mut fields = StateUpdateFields::default();
+                event.record(&mut fields);
+                if !fields.is_valid() {
+                    return;
                 }
+
+                let Some(iid) = find_iid_in_scope::<ResourceSpan, _>(ctx.event_span(event)) else {
+                    return;
+                };
+                let update = fields.into_state_update(iid);
+                self.write_record(
+                    timestamp,
+                    chunked::RecordData::ResourceStateUpdate { update },
+                );
+            }
+            TraceKind::Event(EventKind::AsyncOpUpdate) => {
+                let mut fields = StateUpdateFields::default();
+                event.record(&mut fields);
+                if !fields.is_valid() {
+                    return;
+                }
+
+                let Some(iid) = find_iid_in_scope::<AsyncOpSpan, _>(ctx.event_span(event)) else {
+                    return;
+                };
+                let update = fields.into_state_update(iid);
+                self.write_record(
+                    timestamp,
+                    chunked::RecordData::AsyncOpStateUpdate { update },
+                );
+            }
+            TraceKind::Event(EventKind::Generic) => {
+                let mut fields = FieldValues::new(event.metadata());
+                event.record(&mut fields);
+                let (split_field_values, dynamic_fields) = fields.into_parts();
+
+                let event = rfr::Event {
+                    callsite_id,
+                    parent: get_parent(event.is_root(), ctx.event_span(event), |extensions| {
+                        self.categories.includes_span(extensions)
+                    }),
+                    split_field_values,
+                    dynamic_fields,
+                };
+                self.write_record(timestamp, chunked::RecordData::Event { event });
             }
             _ => {
                 // Not yet implemented
@@ -282,10 +1013,22 @@ where
     fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
         let timestamp = AbsTimestamp::now();
         let span = ctx.span(id).expect("enter {id:?} not found, this is a bug");
+        let Some(iid) = to_iid(&span) else {
+            return;
+        };
         let extensions = span.extensions();
         if extensions.get::<TaskId>().is_some() {
             // This is a runtime.spawn span
-            let poll_start = chunked::RecordData::TaskPollStart { iid: to_iid(id) };
+            POLLING_TASKS.with_borrow_mut(|tasks| tasks.push(iid));
+            let poll_start = chunked::RecordData::TaskPollStart { iid };
+            self.write_record(timestamp, poll_start);
+        } else if extensions.get::<GenericSpan>().is_some() {
+            let span_enter = chunked::RecordData::SpanEnter { iid };
+            self.write_record(timestamp, span_enter);
+        } else if let Some(poll_span) = extensions.get::<AsyncOpPollSpan>() {
+            let poll_start = chunked::RecordData::AsyncOpPollStart {
+                iid: poll_span.async_op_iid,
+            };
             self.write_record(timestamp, poll_start);
         }
     }
@@ -293,10 +1036,26 @@ where
     fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
         let timestamp = AbsTimestamp::now();
         let span = ctx.span(id).expect("exit {id:?} not found, this is a bug");
+        let Some(iid) = to_iid(&span) else {
+            return;
+        };
         let extensions = span.extensions();
         if extensions.get::<TaskId>().is_some() {
             // This is a runtime.spawn span
-            let poll_end = chunked::RecordData::TaskPollEnd { iid: to_iid(id) };
+            POLLING_TASKS.with_borrow_mut(|tasks| {
+                if let Some(idx) = tasks.iter().rposition(|task_iid| task_iid == &iid) {
+                    tasks.remove(idx);
+                }
+            });
+            let poll_end = chunked::RecordData::TaskPollEnd { iid };
+            self.write_record(timestamp, poll_end);
+        } else if extensions.get::<GenericSpan>().is_some() {
+            let span_exit = chunked::RecordData::SpanExit { iid };
+            self.write_record(timestamp, span_exit);
+        } else if let Some(poll_span) = extensions.get::<AsyncOpPollSpan>() {
+            let poll_end = chunked::RecordData::AsyncOpPollEnd {
+                iid: poll_span.async_op_iid,
+            };
             self.write_record(timestamp, poll_end);
         }
     }
@@ -306,14 +1065,32 @@ where
         let span = ctx
             .span(&id)
             .expect("close {id:?} not found, this is a bug");
+        let Some(iid) = to_iid(&span) else {
+            return;
+        };
         let extensions = span.extensions();
         if extensions.get::<TaskId>().is_some() {
             // This is a runtime.spawn span
-            let iid = to_iid(&id);
+            self.task_iids.close(&id);
             let task_drop = chunked::RecordData::TaskDrop { iid };
 
             self.write_record(timestamp, task_drop);
             self.drop_object(&iid);
+        } else if extensions.get::<GenericSpan>().is_some() {
+            let span_close = chunked::RecordData::SpanClose { iid };
+
+            self.write_record(timestamp, span_close);
+            self.drop_object(&iid);
+        } else if extensions.get::<ResourceSpan>().is_some() {
+            let resource_drop = chunked::RecordData::ResourceDrop { iid };
+
+            self.write_record(timestamp, resource_drop);
+            self.drop_object(&iid);
+        } else if extensions.get::<AsyncOpSpan>().is_some() {
+            let async_op_drop = chunked::RecordData::AsyncOpDrop { iid };
+
+            self.write_record(timestamp, async_op_drop);
+            self.drop_object(&iid);
         }
     }
 }
@@ -1,12 +1,25 @@
-use std::{error, fmt, ptr};
+use std::{
+    collections::{HashMap, VecDeque},
+    error, fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
-use rfr::{Callsite, CallsiteId, Field, FieldName, FieldValue, InstrumentationId};
+use rfr::{
+    AsyncOp, Callsite, CallsiteId, Field, FieldName, FieldValue, InstrumentationId, Location,
+    Parent, PollOp, Resource, ResourceKind, StateUpdate, StateUpdateOp,
+};
 use tracing::{
-    Level, Metadata, Subscriber,
+    Level, Metadata, Subscriber, callsite,
     field::{self, Visit},
     span,
 };
-use tracing_subscriber::{layer::Context, registry::LookupSpan};
+use tracing_subscriber::{
+    layer::Context,
+    registry::{Extensions, LookupSpan, SpanRef},
+};
 
 #[derive(Clone)]
 pub(super) enum TraceKind {
@@ -20,6 +33,7 @@ pub(super) enum SpanKind {
     Resource,
     AsyncOp,
     AsyncOpPoll,
+    Generic,
 }
 
 #[derive(Clone)]
@@ -28,6 +42,7 @@ pub(super) enum EventKind {
     PollOp,
     ResourceStateUpdate,
     AsyncOpUpdate,
+    Generic,
 }
 
 impl From<SpanKind> for TraceKind {
@@ -52,11 +67,7 @@ impl TryFrom<&Metadata<'_>> for TraceKind {
                 ("runtime.resource", _) => SpanKind::Resource,
                 ("runtime.resource.async_op", _) => SpanKind::AsyncOp,
                 ("runtime.resource.async_op.poll", _) => SpanKind::AsyncOpPoll,
-                _ => {
-                    return Err(TryFromMetadataError {
-                        desc: "span metadata isn't interesting",
-                    });
-                }
+                _ => SpanKind::Generic,
             }
             .into())
         } else if metadata.is_event() {
@@ -65,11 +76,7 @@ impl TryFrom<&Metadata<'_>> for TraceKind {
                 "runtime::resource::poll_op" => EventKind::PollOp,
                 "runtime::resource::state_update" => EventKind::ResourceStateUpdate,
                 "runtime::resource::async_op::state_update" => EventKind::AsyncOpUpdate,
-                _ => {
-                    return Err(TryFromMetadataError {
-                        desc: "event metadata isn't interesting",
-                    });
-                }
+                _ => EventKind::Generic,
             }
             .into())
         } else {
@@ -99,7 +106,7 @@ pub(super) fn to_callsite(metadata: &Metadata<'_>) -> Callsite {
         },
         Field {
             name: FieldName("target".into()),
-            value: FieldValue::Str(metadata.name().to_string()),
+            value: FieldValue::Str(metadata.target().to_string()),
         },
     ];
     if let Some(module_path) = metadata.module_path() {
@@ -144,20 +151,220 @@ pub(super) fn to_callsite(metadata: &Metadata<'_>) -> Callsite {
     }
 }
 
+/// Derive a stable callsite Id from the static parts of the metadata.
+///
+/// This is more expensive than looking up a callsite by its [`callsite::Identifier`], so it should
+/// only be called when a callsite is registered.
 pub(super) fn to_callsite_id(metadata: &Metadata<'_>) -> CallsiteId {
-    CallsiteId::from(ptr::from_ref(metadata) as u64)
+    CallsiteId::from_static_parts(
+        metadata.name(),
+        metadata.target(),
+        metadata.module_path(),
+        metadata.file(),
+        metadata.line(),
+        metadata.fields().iter().map(|field| field.name()),
+    )
+}
+
+/// The instrumentation Id of a span.
+///
+/// The registry reuses span Ids once a span has closed, so they can't be used as iids directly.
+/// Instead, each span is assigned an iid from a counter when it is created, which makes the iid
+/// unique for the lifetime of the process. The iid is stored in the span's extensions.
+#[derive(Clone, Copy, Debug)]
+struct SpanIid(InstrumentationId);
+
+/// The next iid to assign, shared by all layers so that iids are unique within the process.
+static NEXT_IID: AtomicU64 = AtomicU64::new(1);
+
+fn next_iid() -> InstrumentationId {
+    InstrumentationId::from(NEXT_IID.fetch_add(1, Ordering::Relaxed))
 }
 
-pub(super) fn to_iid(span_id: &span::Id) -> InstrumentationId {
-    InstrumentationId::from(span_id.into_u64())
+/// Assign an iid to a new span, unless another layer has already done so.
+///
+/// This must be called for every new span before [`to_iid`] is called for it.
+pub(super) fn assign_iid<S>(span: &SpanRef<'_, S>) -> InstrumentationId
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    let mut extensions = span.extensions_mut();
+    if let Some(SpanIid(iid)) = extensions.get_mut::<SpanIid>() {
+        return *iid;
+    }
+    let iid = next_iid();
+    extensions.insert(SpanIid(iid));
+    iid
 }
 
+/// Returns the iid assigned to a span.
+///
+/// A span which the layer didn't see being created has no iid, in which case `None` is returned.
+/// The span's extensions mustn't be locked when this function is called.
+pub(super) fn to_iid<S>(span: &SpanRef<'_, S>) -> Option<InstrumentationId>
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    span.extensions().get::<SpanIid>().map(|SpanIid(iid)| *iid)
+}
+
+/// Returns the iid for a task which can no longer be found, by the task's span Id.
+///
+/// The iid is the span Id with the highest bit set. Iids assigned from the counter never get that
+/// high, so the iid doesn't belong to any object and readers will treat the task as unknown. All
+/// records for the same span Id get the same iid.
+fn unresolved_task_iid(task_span_id: &span::Id) -> InstrumentationId {
+    const UNRESOLVED_BIT: u64 = 1 << 63;
+
+    InstrumentationId::from(task_span_id.into_u64() | UNRESOLVED_BIT)
+}
+
+/// Returns the iid of the task which the current span is within (if any).
+///
+/// The current span may be a generic span which was entered while the task was being polled, so
+/// we look at the whole scope for the closest task span.
 pub(crate) fn get_context_task_iid<S>(ctx: &Context<'_, S>) -> Option<InstrumentationId>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    let current_span = &ctx.current_span();
-    Some(to_iid(current_span.id()?))
+    ctx.lookup_current()?
+        .scope()
+        .find(|span| span.extensions().get::<TaskId>().is_some())
+        .and_then(|span| to_iid(&span))
+}
+
+/// Returns the parent of a new span or event.
+///
+/// The parent is the closest span in the scope starting at `parent_span` which is recorded, any
+/// spans which aren't recorded are skipped. A span is recorded if it is instrumented and
+/// `is_recorded` returns true for its extensions.
+pub(crate) fn get_parent<S>(
+    is_root: bool,
+    parent_span: Option<SpanRef<'_, S>>,
+    is_recorded: impl Fn(&Extensions<'_>) -> bool,
+) -> Parent
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if is_root {
+        return Parent::Root;
+    }
+
+    parent_span
+        .and_then(|parent_span| {
+            parent_span.scope().find(|span| {
+                let extensions = span.extensions();
+                let is_instrumented = extensions.get::<TaskId>().is_some()
+                    || extensions.get::<GenericSpan>().is_some()
+                    || extensions.get::<ResourceSpan>().is_some()
+                    || extensions.get::<AsyncOpSpan>().is_some();
+                is_instrumented && is_recorded(&extensions)
+            })
+        })
+        .and_then(|span| to_iid(&span))
+        .map(|iid| Parent::Explicit { iid })
+        .unwrap_or(Parent::Root)
+}
+
+/// Marker for a generic span that is being recorded.
+///
+/// This is stored in the span's extensions.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GenericSpan;
+
+/// Field values for a span or event.
+///
+/// Values are stored in the order of the fields in the metadata, which is the same order as the
+/// `split_field_names` in the callsite created by [`to_callsite`].
+#[derive(Debug)]
+pub(crate) struct FieldValues {
+    callsite: callsite::Identifier,
+    split_values: Vec<Option<Field>>,
+    dynamic_fields: Vec<Field>,
+}
+
+impl FieldValues {
+    pub(crate) fn new(metadata: &Metadata<'_>) -> Self {
+        Self {
+            callsite: metadata.callsite(),
+            split_values: vec![None; metadata.fields().len()],
+            dynamic_fields: Vec::new(),
+        }
+    }
+
+    /// Split the field values into split field values and dynamic fields.
+    ///
+    /// If a value wasn't recorded for one or more fields, then there are no split field values
+    /// and all the recorded values are returned as dynamic fields.
+    pub(crate) fn into_parts(self) -> (Vec<FieldValue>, Vec<Field>) {
+        let Self {
+            split_values,
+            mut dynamic_fields,
+            ..
+        } = self;
+
+        if split_values.iter().all(Option::is_some) {
+            let split_field_values = split_values
+                .into_iter()
+                .flatten()
+                .map(|field| field.value)
+                .collect();
+            (split_field_values, dynamic_fields)
+        } else {
+            let mut fields: Vec<Field> = split_values.into_iter().flatten().collect();
+            fields.append(&mut dynamic_fields);
+            (Vec::new(), fields)
+        }
+    }
+
+    fn record_value(&mut self, field: &field::Field, value: FieldValue) {
+        let new_field = Field {
+            name: FieldName(field.name().into()),
+            value,
+        };
+        match self.split_values.get_mut(field.index()) {
+            Some(slot) if field.callsite() == self.callsite => *slot = Some(new_field),
+            _ => self.dynamic_fields.push(new_field),
+        }
+    }
+}
+
+impl Visit for FieldValues {
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.record_value(field, FieldValue::F64(value));
+    }
+
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.record_value(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        self.record_value(field, FieldValue::U64(value));
+    }
+
+    fn record_i128(&mut self, field: &field::Field, value: i128) {
+        self.record_value(field, FieldValue::I128(value));
+    }
+
+    fn record_u128(&mut self, field: &field::Field, value: u128) {
+        self.record_value(field, FieldValue::U128(value));
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.record_value(field, FieldValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        self.record_value(field, FieldValue::Str(value.to_owned()));
+    }
+
+    fn record_error(&mut self, field: &field::Field, value: &(dyn error::Error + 'static)) {
+        self.record_value(field, FieldValue::Str(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        self.record_value(field, FieldValue::Str(format!("{value:?}")));
+    }
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -170,6 +377,8 @@ pub(crate) struct SpawnSpan {
     pub(crate) task_kind: TaskKind,
 
     pub(crate) context: Option<InstrumentationId>,
+    pub(crate) location: Option<Location>,
+    pub(crate) fields: Vec<Field>,
 
     pub(crate) callsite_id: CallsiteId,
     pub(crate) iid: InstrumentationId,
@@ -178,20 +387,26 @@ pub(crate) struct SpawnSpan {
 impl SpawnSpan {
     pub(crate) fn new(
         callsite_id: CallsiteId,
-        span_id: span::Id,
+        iid: InstrumentationId,
         context: Option<InstrumentationId>,
         fields: SpawnFields,
     ) -> Self {
         debug_assert!(fields.is_valid(), "invalid fields passed to SpawnSpan::new");
+        let location = match (fields.loc_file, fields.loc_line, fields.loc_col) {
+            (Some(file), Some(line), Some(col)) => Some(Location { file, line, col }),
+            _ => None,
+        };
         Self {
             task_id: fields.task_id.unwrap(),
             task_name: fields.task_name.unwrap_or_default(),
             task_kind: fields.task_kind.unwrap(),
 
             context,
+            location,
+            fields: fields.other_fields,
 
             callsite_id,
-            iid: to_iid(&span_id),
+            iid,
         }
     }
 }
@@ -222,32 +437,371 @@ pub(crate) struct SpawnFields {
     task_id: Option<TaskId>,
     task_name: Option<String>,
     task_kind: Option<TaskKind>,
+    loc_file: Option<String>,
+    loc_line: Option<u32>,
+    loc_col: Option<u32>,
+    /// Fields which aren't stored elsewhere on the task.
+    other_fields: Vec<Field>,
 }
 
 impl SpawnFields {
     const TASK_ID: &'static str = "task.id";
     const TASK_NAME: &'static str = "task.name";
     const KIND: &'static str = "kind";
+    const LOC_FILE: &'static str = "loc.file";
+    const LOC_LINE: &'static str = "loc.line";
+    const LOC_COL: &'static str = "loc.col";
 
     pub(crate) fn is_valid(&self) -> bool {
         self.task_id.is_some() && self.task_kind.is_some()
     }
+
+    fn record_other(&mut self, field: &field::Field, value: FieldValue) {
+        self.other_fields.push(Field {
+            name: FieldName(field.name().into()),
+            value,
+        });
+    }
 }
 
 impl Visit for SpawnFields {
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.record_other(field, FieldValue::F64(value));
+    }
+
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.record_other(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        match field.name() {
+            Self::TASK_ID => self.task_id = Some(TaskId(value)),
+            Self::LOC_LINE => self.loc_line = Some(value as u32),
+            Self::LOC_COL => self.loc_col = Some(value as u32),
+            _ => self.record_other(field, FieldValue::U64(value)),
+        }
+    }
+
+    fn record_i128(&mut self, field: &field::Field, value: i128) {
+        self.record_other(field, FieldValue::I128(value));
+    }
+
+    fn record_u128(&mut self, field: &field::Field, value: u128) {
+        self.record_other(field, FieldValue::U128(value));
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.record_other(field, FieldValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        match field.name() {
+            Self::TASK_NAME => self.task_name = Some(value.to_owned()),
+            Self::KIND => self.task_kind = Some(value.to_owned().into()),
+            Self::LOC_FILE => self.loc_file = Some(value.to_owned()),
+            _ => self.record_other(field, FieldValue::Str(value.to_owned())),
+        }
+    }
+
     fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
         match field.name() {
             Self::TASK_NAME => self.task_name = Some(format!("{value:?}")),
             Self::KIND => self.task_kind = Some(format!("{value:?}").into()),
+            Self::LOC_FILE => self.loc_file = Some(format!("{value:?}")),
+            _ => self.record_other(field, FieldValue::Str(format!("{value:?}"))),
+        }
+    }
+}
+
+/// Marker for a resource span that is being recorded.
+///
+/// This is stored in the span's extensions.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResourceSpan;
+
+#[derive(Debug, Default)]
+pub(crate) struct ResourceFields {
+    concrete_type: Option<String>,
+    kind: Option<String>,
+    is_internal: bool,
+    loc_file: Option<String>,
+    loc_line: Option<u32>,
+    loc_col: Option<u32>,
+}
+
+impl ResourceFields {
+    const CONCRETE_TYPE: &'static str = "concrete_type";
+    const KIND: &'static str = "kind";
+    const IS_INTERNAL: &'static str = "is_internal";
+    const LOC_FILE: &'static str = "loc.file";
+    const LOC_LINE: &'static str = "loc.line";
+    const LOC_COL: &'static str = "loc.col";
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.concrete_type.is_some() && self.kind.is_some()
+    }
+
+    pub(crate) fn into_resource(self, iid: InstrumentationId, callsite_id: CallsiteId) -> Resource {
+        debug_assert!(self.is_valid(), "invalid fields passed to into_resource");
+        let kind = match self.kind.unwrap_or_default().as_str() {
+            "timer" => ResourceKind::Timer,
+            "Sync" => ResourceKind::Sync,
+            other => ResourceKind::Other(other.to_owned()),
+        };
+        let location = match (self.loc_file, self.loc_line, self.loc_col) {
+            (Some(file), Some(line), Some(col)) => Some(Location { file, line, col }),
+            _ => None,
+        };
+
+        Resource {
+            iid,
+            callsite_id,
+            concrete_type: self.concrete_type.unwrap_or_default(),
+            kind,
+            location,
+            is_internal: self.is_internal,
+        }
+    }
+}
+
+impl Visit for ResourceFields {
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            Self::CONCRETE_TYPE => self.concrete_type = Some(format!("{value:?}")),
+            Self::KIND => self.kind = Some(format!("{value:?}")),
+            Self::LOC_FILE => self.loc_file = Some(format!("{value:?}")),
+            _ => {}
+        }
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        match field.name() {
+            Self::CONCRETE_TYPE => self.concrete_type = Some(value.to_owned()),
+            Self::KIND => self.kind = Some(value.to_owned()),
+            Self::LOC_FILE => self.loc_file = Some(value.to_owned()),
             _ => {}
         }
     }
 
     fn record_u64(&mut self, field: &field::Field, value: u64) {
-        if field.name() == Self::TASK_ID {
-            self.task_id = Some(TaskId(value));
+        match field.name() {
+            Self::LOC_LINE => self.loc_line = Some(value as u32),
+            Self::LOC_COL => self.loc_col = Some(value as u32),
+            _ => {}
         }
     }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        if field.name() == Self::IS_INTERNAL {
+            self.is_internal = value;
+        }
+    }
+}
+
+/// Marker for an async op span that is being recorded.
+///
+/// This is stored in the span's extensions.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AsyncOpSpan;
+
+/// The async op that an async op poll span belongs to.
+///
+/// This is stored in the extensions of an async op poll span, so that polls can be recorded
+/// against the parent async op.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AsyncOpPollSpan {
+    pub(crate) async_op_iid: InstrumentationId,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AsyncOpFields {
+    source: Option<String>,
+}
+
+impl AsyncOpFields {
+    const SOURCE: &'static str = "source";
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.source.is_some()
+    }
+
+    pub(crate) fn into_async_op(
+        self,
+        iid: InstrumentationId,
+        callsite_id: CallsiteId,
+        resource_iid: InstrumentationId,
+        context: Option<InstrumentationId>,
+    ) -> AsyncOp {
+        debug_assert!(self.is_valid(), "invalid fields passed to into_async_op");
+        AsyncOp {
+            iid,
+            callsite_id,
+            source: self.source.unwrap_or_default(),
+            resource_iid,
+            context,
+        }
+    }
+}
+
+impl Visit for AsyncOpFields {
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        if field.name() == Self::SOURCE {
+            self.source = Some(format!("{value:?}"));
+        }
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        if field.name() == Self::SOURCE {
+            self.source = Some(value.to_owned());
+        }
+    }
+}
+
+/// Returns the iid of the closest span in the scope starting at `span` which has an extension of
+/// type `E`.
+pub(crate) fn find_iid_in_scope<E, S>(span: Option<SpanRef<'_, S>>) -> Option<InstrumentationId>
+where
+    E: 'static,
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    span?
+        .scope()
+        .find(|span| span.extensions().get::<E>().is_some())
+        .and_then(|span| to_iid(&span))
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PollOpFields {
+    op_name: Option<String>,
+    is_ready: Option<bool>,
+}
+
+impl PollOpFields {
+    const OP_NAME: &'static str = "op_name";
+    const IS_READY: &'static str = "is_ready";
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.op_name.is_some() && self.is_ready.is_some()
+    }
+
+    pub(crate) fn into_poll_op(
+        self,
+        resource_iid: InstrumentationId,
+        async_op_iid: Option<InstrumentationId>,
+        context: Option<InstrumentationId>,
+    ) -> PollOp {
+        debug_assert!(self.is_valid(), "invalid fields passed to into_poll_op");
+        PollOp {
+            resource_iid,
+            async_op_iid,
+            op_name: self.op_name.unwrap_or_default(),
+            is_ready: self.is_ready.unwrap_or_default(),
+            context,
+        }
+    }
+}
+
+impl Visit for PollOpFields {
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        if field.name() == Self::OP_NAME {
+            self.op_name = Some(format!("{value:?}"));
+        }
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        if field.name() == Self::OP_NAME {
+            self.op_name = Some(value.to_owned());
+        }
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        if field.name() == Self::IS_READY {
+            self.is_ready = Some(value);
+        }
+    }
+}
+
+/// Fields for a resource or async op state update.
+///
+/// A state update event contains a single attribute, optionally accompanied by fields for the
+/// unit and the operation, named with the suffixes `.unit` and `.op`. The attribute name and the
+/// prefix of the other fields don't always match (e.g. `permits_obtained` and `permits.op`), so
+/// the unit and op are taken from whichever fields have those suffixes.
+#[derive(Debug, Default)]
+pub(crate) struct StateUpdateFields {
+    attribute: Option<(FieldName, FieldValue)>,
+    unit: Option<String>,
+    op: Option<StateUpdateOp>,
+}
+
+impl StateUpdateFields {
+    const UNIT_SUFFIX: &'static str = ".unit";
+    const OP_SUFFIX: &'static str = ".op";
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.attribute.is_some()
+    }
+
+    pub(crate) fn into_state_update(self, iid: InstrumentationId) -> StateUpdate {
+        debug_assert!(
+            self.is_valid(),
+            "invalid fields passed to into_state_update"
+        );
+        let (attribute, value) = self
+            .attribute
+            .unwrap_or_else(|| (FieldName(String::new()), FieldValue::U64(0)));
+        StateUpdate {
+            iid,
+            attribute,
+            value,
+            unit: self.unit,
+            op: self.op,
+        }
+    }
+
+    fn record_attribute(&mut self, field: &field::Field, value: FieldValue) {
+        self.attribute = Some((FieldName(field.name().into()), value));
+    }
+
+    fn record_string(&mut self, field: &field::Field, value: String) {
+        let name = field.name();
+        if name.ends_with(Self::UNIT_SUFFIX) {
+            self.unit = Some(value);
+        } else if name.ends_with(Self::OP_SUFFIX) {
+            self.op = match value.as_str() {
+                "override" => Some(StateUpdateOp::Override),
+                "add" => Some(StateUpdateOp::Add),
+                "sub" => Some(StateUpdateOp::Sub),
+                _ => None,
+            };
+        }
+    }
+}
+
+impl Visit for StateUpdateFields {
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.record_attribute(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        self.record_attribute(field, FieldValue::U64(value));
+    }
+
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.record_attribute(field, FieldValue::F64(value));
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.record_attribute(field, FieldValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        self.record_string(field, value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        self.record_string(field, format!("{value:?}"));
+    }
 }
 
 #[derive(Debug)]
@@ -315,3 +869,215 @@ impl Visit for WakerFields {
         }
     }
 }
+
+/// The iids of the task spans which are open or were closed recently, by span Id.
+///
+/// Waker events refer to their task by span Id. Wakers are often woken or dropped after their task
+/// has been closed, when the registry can no longer find the span (or has given its Id to another
+/// span). So the iids of closed tasks are kept until their span Id is given to a new task, or until
+/// [`MAX_CLOSED_TASKS`] more tasks have been closed.
+#[derive(Debug, Default)]
+pub(crate) struct TaskIids {
+    inner: Mutex<TaskIidsInner>,
+}
+
+/// How many closed tasks are kept in [`TaskIids`].
+const MAX_CLOSED_TASKS: usize = 4096;
+
+#[derive(Debug, Default)]
+struct TaskIidsInner {
+    iids: HashMap<span::Id, InstrumentationId>,
+    /// The closed tasks, oldest first.
+    closed: VecDeque<(span::Id, InstrumentationId)>,
+}
+
+/// The task that a waker event refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WakerTask {
+    /// The task is open.
+    Open(InstrumentationId),
+    /// The task has been closed, or couldn't be found at all.
+    Closed(InstrumentationId),
+}
+
+impl WakerTask {
+    pub(crate) fn iid(&self) -> InstrumentationId {
+        match self {
+            Self::Open(iid) | Self::Closed(iid) => *iid,
+        }
+    }
+}
+
+impl TaskIids {
+    fn lock(&self) -> std::sync::MutexGuard<'_, TaskIidsInner> {
+        self.inner.lock().expect("task iids poisoned")
+    }
+
+    /// Store the iid of a new task, replacing any closed task which had the same span Id.
+    pub(crate) fn insert(&self, span_id: span::Id, iid: InstrumentationId) {
+        self.lock().iids.insert(span_id, iid);
+    }
+
+    /// Mark a task as closed, its iid is kept for waker events which arrive afterwards.
+    pub(crate) fn close(&self, span_id: &span::Id) {
+        let mut inner = self.lock();
+        let Some(iid) = inner.iids.get(span_id).copied() else {
+            return;
+        };
+        inner.closed.push_back((span_id.clone(), iid));
+        if inner.closed.len() > MAX_CLOSED_TASKS
+            && let Some((oldest_id, oldest_iid)) = inner.closed.pop_front()
+            && inner.iids.get(&oldest_id) == Some(&oldest_iid)
+        {
+            // The span Id hasn't been given to a new task since.
+            inner.iids.remove(&oldest_id);
+        }
+    }
+
+    /// Returns the task a waker event refers to.
+    ///
+    /// A task which can't be found any more is given an unresolved iid, which is the same for all
+    /// the waker events for that span Id.
+    pub(crate) fn waker_task<S>(&self, ctx: &Context<'_, S>, task_span_id: &span::Id) -> WakerTask
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        // The span Id may have been reused by a span which isn't a task.
+        if let Some(task_span) = ctx.span(task_span_id)
+            && task_span.extensions().get::<TaskId>().is_some()
+            && let Some(iid) = to_iid(&task_span)
+        {
+            return WakerTask::Open(iid);
+        }
+
+        let iid = self.lock().iids.get(task_span_id).copied();
+        WakerTask::Closed(iid.unwrap_or_else(|| unresolved_task_iid(task_span_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tracing::{Event, span, subscriber::with_default};
+    use tracing_subscriber::{Layer, layer::SubscriberExt, registry::Registry};
+
+    use super::*;
+
+    /// Resolves the task of every waker event in the same way as the RFR layers.
+    #[derive(Default)]
+    struct WakerLayer {
+        task_iids: TaskIids,
+        resolved: Arc<Mutex<Vec<WakerTask>>>,
+    }
+
+    impl<S> Layer<S> for WakerLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let iid = assign_iid(&span);
+            if attrs.metadata().name() == "task" {
+                span.extensions_mut().insert(TaskId(1));
+                self.task_iids.insert(id.clone(), iid);
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = WakerFields::default();
+            event.record(&mut fields);
+            let task = self
+                .task_iids
+                .waker_task(&ctx, &fields.task_span_id.unwrap());
+            self.resolved.lock().unwrap().push(task);
+        }
+
+        fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+            let span = ctx.span(&id).unwrap();
+            if span.extensions().get::<TaskId>().is_some() {
+                self.task_iids.close(&id);
+            }
+        }
+    }
+
+    fn wake(task_span_id: &span::Id) {
+        tracing::trace!(task.id = task_span_id.into_u64(), op = "waker.drop");
+    }
+
+    /// Creates and closes a task, returning its span Id and iid.
+    fn closed_task() -> (span::Id, InstrumentationId) {
+        let task = tracing::trace_span!("task");
+        let task_span_id = task.id().unwrap();
+        let task_iid = current_iid(&task_span_id).unwrap();
+        (task_span_id, task_iid)
+    }
+
+    fn current_iid(span_id: &span::Id) -> Option<InstrumentationId> {
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch.downcast_ref::<Registry>().unwrap();
+            to_iid(&registry.span(span_id).unwrap())
+        })
+    }
+
+    #[test]
+    fn waker_resolves_open_task() {
+        let layer = WakerLayer::default();
+        let resolved = Arc::clone(&layer.resolved);
+
+        let task_iid = with_default(Registry::default().with(layer), || {
+            let task = tracing::trace_span!("task");
+            wake(&task.id().unwrap());
+            current_iid(&task.id().unwrap()).unwrap()
+        });
+
+        assert_eq!(*resolved.lock().unwrap(), vec![WakerTask::Open(task_iid)]);
+    }
+
+    #[test]
+    fn wakers_after_task_closed_have_task_iid() {
+        let layer = WakerLayer::default();
+        let resolved = Arc::clone(&layer.resolved);
+
+        let task_iid = with_default(Registry::default().with(layer), || {
+            let (task_span_id, task_iid) = closed_task();
+            wake(&task_span_id);
+            wake(&task_span_id);
+            wake(&task_span_id);
+            task_iid
+        });
+
+        assert_eq!(
+            *resolved.lock().unwrap(),
+            vec![WakerTask::Closed(task_iid); 3]
+        );
+    }
+
+    #[test]
+    fn wakers_for_unknown_task_share_iid() {
+        let layer = WakerLayer::default();
+        let resolved = Arc::clone(&layer.resolved);
+
+        let (first_task_iid, other_task_iid) =
+            with_default(Registry::default().with(layer), || {
+                let (first_span_id, first_iid) = closed_task();
+                // Close enough tasks afterwards that the first is forgotten.
+                let (mut other_span_id, mut other_iid) = closed_task();
+                for _ in 1..MAX_CLOSED_TASKS {
+                    (other_span_id, other_iid) = closed_task();
+                }
+                wake(&first_span_id);
+                wake(&first_span_id);
+                wake(&other_span_id);
+                (first_iid, other_iid)
+            });
+
+        let resolved = resolved.lock().unwrap();
+        assert_eq!(resolved.len(), 3);
+        // The first task's iid is unresolved, but the same for every waker event.
+        assert!(matches!(resolved[0], WakerTask::Closed(_)));
+        assert_ne!(resolved[0].iid(), first_task_iid);
+        assert_eq!(resolved[0], resolved[1]);
+        assert_eq!(resolved[2], WakerTask::Closed(other_task_iid));
+    }
+}
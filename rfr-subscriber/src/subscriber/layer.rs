@@ -4,7 +4,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use tracing::{Dispatch, Event, Metadata, Subscriber, span, subscriber::Interest};
+use tracing::{Dispatch, Event, Metadata, Subscriber, callsite, span, subscriber::Interest};
 use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
 
 use rfr::{
@@ -13,13 +13,14 @@ use rfr::{
 };
 
 use crate::subscriber::common::{
-    EventKind, SpanKind, SpawnFields, SpawnSpan, TaskId, TaskKind, TraceKind, WakerFields, WakerOp,
-    get_context_task_iid, to_callsite_id, to_iid,
+    EventKind, SpanKind, SpawnFields, SpawnSpan, TaskId, TaskIids, TaskKind, TraceKind,
+    WakerFields, WakerOp, assign_iid, get_context_task_iid, to_callsite_id, to_iid,
 };
 
 pub struct RfrLayer {
     writer: Arc<Mutex<StreamWriter<fs::File>>>,
-    callsite_cache: Mutex<HashMap<CallsiteId, TraceKind>>,
+    callsite_cache: Mutex<HashMap<callsite::Identifier, (CallsiteId, TraceKind)>>,
+    task_iids: TaskIids,
 }
 
 impl RfrLayer {
@@ -32,6 +33,7 @@ impl RfrLayer {
         Self {
             writer,
             callsite_cache: Default::default(),
+            task_iids: Default::default(),
         }
     }
 
@@ -67,29 +69,38 @@ where
 
     fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
         match TraceKind::try_from(metadata) {
+            // Generic spans and events aren't supported in the streaming format. They aren't
+            // cached, so they're skipped, but other layers in the subscriber may still want them.
+            Ok(TraceKind::Span(SpanKind::Generic)) | Ok(TraceKind::Event(EventKind::Generic)) => {
+                Interest::always()
+            }
+            Err(_) => Interest::never(),
             Ok(kind) => {
-                let callsite_id = to_callsite_id(metadata);
                 let mut callsite_cache = self
                     .callsite_cache
                     .lock()
                     .expect("callsite cache is poisoned");
-                callsite_cache.entry(callsite_id).or_insert(kind);
+                callsite_cache
+                    .entry(metadata.callsite())
+                    .or_insert_with(|| (to_callsite_id(metadata), kind));
 
                 Interest::always()
             }
-            Err(_) => Interest::never(),
         }
     }
 
     fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
         let rec_meta = Meta::now();
-        let callsite_id = to_callsite_id(attrs.metadata());
-        let kind = {
+        let span = ctx
+            .span(id)
+            .expect("new_span {id:?} not found, this is a bug");
+        let iid = assign_iid(&span);
+        let (callsite_id, kind) = {
             let callsite_cache = self.callsite_cache.lock().expect("callsite cache poisoned");
-            let Some(kind) = callsite_cache.get(&callsite_id).cloned() else {
+            let Some(entry) = callsite_cache.get(&attrs.metadata().callsite()).cloned() else {
                 return;
             };
-            kind
+            entry
         };
         match kind {
             TraceKind::Span(SpanKind::Spawn) => {
@@ -100,15 +111,13 @@ where
                 }
                 let context = get_context_task_iid(&ctx);
 
-                let spawn = SpawnSpan::new(callsite_id, id.clone(), context, fields);
+                let spawn = SpawnSpan::new(callsite_id, iid, context, fields);
 
-                let span = ctx
-                    .span(id)
-                    .expect("new_span {id:?} not found, this is a bug");
                 let mut extensions = span.extensions_mut();
                 if extensions.get_mut::<TaskId>().is_none() {
                     extensions.insert(spawn.task_id);
                 }
+                self.task_iids.insert(id.clone(), spawn.iid);
                 {
                     let mut guard = self.writer.lock().unwrap();
                     let task_id = rfr::TaskId::from(spawn.task_id.0);
@@ -127,6 +136,8 @@ where
                             },
 
                             context: spawn.context,
+                            location: spawn.location,
+                            fields: spawn.fields,
                         },
                     };
                     let task_new = RecordData::TaskNew { iid: spawn.iid };
@@ -143,10 +154,9 @@ where
 
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
         let rec_meta = Meta::now();
-        let callsite_id = to_callsite_id(event.metadata());
         let kind = {
             let callsite_cache = self.callsite_cache.lock().expect("callsite cache poisoned");
-            let Some(kind) = callsite_cache.get(&callsite_id).cloned() else {
+            let Some((_, kind)) = callsite_cache.get(&event.metadata().callsite()).cloned() else {
                 return;
             };
             kind
@@ -163,8 +173,8 @@ where
 
                 let mut guard = self.writer.lock().unwrap();
                 let waker = rfr::Waker {
-                    task_iid: to_iid(&task_span_id),
-                    context: ctx.current_span().id().map(to_iid),
+                    task_iid: self.task_iids.waker_task(&ctx, &task_span_id).iid(),
+                    context: ctx.lookup_current().and_then(|span| to_iid(&span)),
                 };
                 let waker_data = match op {
                     WakerOp::Wake => RecordData::WakerWake { waker },
@@ -184,11 +194,14 @@ where
     fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
         let rec_meta = Meta::now();
         let span = ctx.span(id).expect("enter {id:?} not found, this is a bug");
+        let Some(iid) = to_iid(&span) else {
+            return;
+        };
         let extensions = span.extensions();
         if extensions.get::<TaskId>().is_some() {
             // This is a runtime.spawn span
             let mut guard = self.writer.lock().unwrap();
-            let poll_start = RecordData::TaskPollStart { iid: to_iid(id) };
+            let poll_start = RecordData::TaskPollStart { iid };
 
             guard.write_record(Record::new(rec_meta, poll_start));
         }
@@ -197,11 +210,14 @@ where
     fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
         let rec_meta = Meta::now();
         let span = ctx.span(id).expect("exit {id:?} not found, this is a bug");
+        let Some(iid) = to_iid(&span) else {
+            return;
+        };
         let extensions = span.extensions();
         if extensions.get::<TaskId>().is_some() {
             // This is a runtime.spawn span
             let mut guard = self.writer.lock().unwrap();
-            let poll_end = RecordData::TaskPollEnd { iid: to_iid(id) };
+            let poll_end = RecordData::TaskPollEnd { iid };
 
             (*guard).write_record(Record::new(rec_meta, poll_end));
         }
@@ -212,11 +228,15 @@ where
         let span = ctx
             .span(&id)
             .expect("close {id:?} not found, this is a bug");
+        let Some(iid) = to_iid(&span) else {
+            return;
+        };
         let extensions = span.extensions();
         if extensions.get::<TaskId>().is_some() {
             // This is a runtime.spawn span
+            self.task_iids.close(&id);
             let mut guard = self.writer.lock().unwrap();
-            let task_drop = RecordData::TaskDrop { iid: to_iid(&id) };
+            let task_drop = RecordData::TaskDrop { iid };
 
             (*guard).write_record(Record::new(rec_meta, task_drop));
         }
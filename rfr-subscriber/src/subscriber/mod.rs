@@ -1,6 +1,11 @@
 mod chunked;
 mod common;
 mod layer;
+mod trigger;
 
-pub use chunked::RfrChunkedLayer;
+pub use chunked::{
+    BuildError, CompletionGuard, DumpError, Dumper, LayerStats, RfrChunkedLayer,
+    RfrChunkedLayerBuilder, StatsHandle,
+};
 pub use layer::RfrLayer;
+pub use trigger::{Trigger, TriggerConfig};
@@ -0,0 +1,517 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    ptr,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use rfr::{
+    AbsTimestamp, InstrumentationId,
+    chunked::{ChunkedWriter, RecordData},
+};
+
+/// A condition which triggers a capture of the in-memory recording.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// A single poll of a task took longer than the duration.
+    LongPoll(Duration),
+    /// A task was woken, but wasn't polled within the duration.
+    ScheduledNotPolled(Duration),
+    /// The number of live tasks exceeded the count.
+    LiveTasks(usize),
+}
+
+impl Trigger {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::LongPoll(_) => "long-poll",
+            Self::ScheduledNotPolled(_) => "scheduled-not-polled",
+            Self::LiveTasks(_) => "live-tasks",
+        }
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LongPoll(threshold) => write!(f, "task poll longer than {threshold:?}"),
+            Self::ScheduledNotPolled(threshold) => {
+                write!(f, "task scheduled but not polled for {threshold:?}")
+            }
+            Self::LiveTasks(threshold) => write!(f, "more than {threshold} live tasks"),
+        }
+    }
+}
+
+/// Configuration for automatic captures.
+///
+/// When one of the triggers fires, a capture is written to a new chunked recording in the captures
+/// directory. The capture covers the window from `before` the moment the trigger fired until
+/// `after` it.
+///
+/// Once a trigger has fired, no trigger will fire again until the `cooldown` has passed, so that a
+/// condition which persists doesn't produce a stream of captures.
+#[derive(Debug, Clone)]
+pub struct TriggerConfig {
+    /// The directory which captured recordings are written into.
+    pub captures_dir: PathBuf,
+    /// The conditions which trigger a capture.
+    pub triggers: Vec<Trigger>,
+    /// The length of the window captured before a trigger fires.
+    pub before: Duration,
+    /// The length of the window captured after a trigger fires.
+    pub after: Duration,
+    /// The minimum time between two triggers firing.
+    pub cooldown: Duration,
+}
+
+impl TriggerConfig {
+    /// Creates a new trigger configuration with no triggers.
+    ///
+    /// By default, 30 seconds before and 5 seconds after a trigger are captured and the cooldown
+    /// is 60 seconds.
+    pub fn new(captures_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            captures_dir: captures_dir.into(),
+            triggers: Vec::new(),
+            before: Duration::from_secs(30),
+            after: Duration::from_secs(5),
+            cooldown: Duration::from_secs(60),
+        }
+    }
+
+    /// Add a trigger.
+    pub fn with_trigger(mut self, trigger: Trigger) -> Self {
+        self.triggers.push(trigger);
+        self
+    }
+
+    /// Set the capture window before and after a trigger fires.
+    pub fn with_window(mut self, before: Duration, after: Duration) -> Self {
+        self.before = before;
+        self.after = after;
+        self
+    }
+
+    /// Set the minimum time between two triggers firing.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+}
+
+thread_local! {
+    /// The start of the task polls which are in progress on this thread.
+    ///
+    /// A poll starts and ends on the same thread, so no synchronization is needed. The key
+    /// includes the address of the [`Triggers`] so that multiple layers don't interfere.
+    static POLL_STARTS: RefCell<HashMap<(usize, InstrumentationId), AbsTimestamp>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Watches records as they are written and captures the recording when a trigger fires.
+///
+/// Observing a record is on the hot path, so only the state which the configured triggers need is
+/// kept. The live task count is atomic, poll starts are kept per thread, and the shared map of
+/// scheduled tasks is only locked if there is a [`Trigger::ScheduledNotPolled`].
+#[derive(Debug)]
+pub(crate) struct Triggers {
+    config: TriggerConfig,
+
+    long_poll: Option<Duration>,
+    scheduled_not_polled: Option<Duration>,
+    live_tasks_threshold: Option<usize>,
+
+    live_tasks: AtomicUsize,
+    /// Whether the live tasks are currently over the threshold, the trigger only fires when the
+    /// threshold is crossed.
+    live_tasks_exceeded: AtomicBool,
+    scheduled: Mutex<HashMap<InstrumentationId, AbsTimestamp>>,
+
+    fire_state: Mutex<FireState>,
+}
+
+#[derive(Debug, Default)]
+struct FireState {
+    pending_capture: Option<PendingCapture>,
+    last_fired_at: Option<AbsTimestamp>,
+}
+
+#[derive(Debug)]
+struct PendingCapture {
+    trigger: Trigger,
+    fired_at: AbsTimestamp,
+}
+
+/// The state of the pending capture.
+#[derive(Debug)]
+enum DueCapture {
+    /// There is no pending capture.
+    None,
+    /// The pending capture is due after the duration.
+    DueIn(Duration),
+    /// The pending capture is due now.
+    Due(PendingCapture),
+}
+
+impl Triggers {
+    pub(crate) fn new(config: TriggerConfig) -> Self {
+        let mut long_poll = None;
+        let mut scheduled_not_polled = None;
+        let mut live_tasks_threshold = None;
+        // If a trigger is configured more than once, the lowest threshold is used.
+        for trigger in &config.triggers {
+            match *trigger {
+                Trigger::LongPoll(threshold) => {
+                    long_poll = Some(long_poll.map_or(threshold, |t: Duration| t.min(threshold)))
+                }
+                Trigger::ScheduledNotPolled(threshold) => {
+                    scheduled_not_polled =
+                        Some(scheduled_not_polled.map_or(threshold, |t: Duration| t.min(threshold)))
+                }
+                Trigger::LiveTasks(threshold) => {
+                    live_tasks_threshold =
+                        Some(live_tasks_threshold.map_or(threshold, |t: usize| t.min(threshold)))
+                }
+            }
+        }
+
+        Self {
+            config,
+            long_poll,
+            scheduled_not_polled,
+            live_tasks_threshold,
+            live_tasks: AtomicUsize::new(0),
+            live_tasks_exceeded: AtomicBool::new(false),
+            scheduled: Mutex::new(HashMap::new()),
+            fire_state: Mutex::new(FireState::default()),
+        }
+    }
+
+    /// Observe a record which is about to be written.
+    pub(crate) fn observe(&self, timestamp: &AbsTimestamp, data: &RecordData) {
+        match data {
+            RecordData::TaskNew { .. } => {
+                let live_tasks = self.live_tasks.fetch_add(1, Ordering::Relaxed) + 1;
+                if let Some(threshold) = self.live_tasks_threshold
+                    && live_tasks > threshold
+                    && !self.live_tasks_exceeded.swap(true, Ordering::Relaxed)
+                {
+                    self.fire(Trigger::LiveTasks(threshold), timestamp);
+                }
+            }
+            RecordData::TaskDrop { iid } => {
+                // Tasks spawned before the recording started may be dropped, so don't underflow.
+                let previous = self
+                    .live_tasks
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live_tasks| {
+                        Some(live_tasks.saturating_sub(1))
+                    })
+                    .unwrap_or_default();
+                if let Some(threshold) = self.live_tasks_threshold
+                    && previous.saturating_sub(1) <= threshold
+                {
+                    self.live_tasks_exceeded.store(false, Ordering::Relaxed);
+                }
+                if self.scheduled_not_polled.is_some() {
+                    self.lock_scheduled().remove(iid);
+                }
+            }
+            RecordData::TaskPollStart { iid } => {
+                if self.long_poll.is_some() {
+                    POLL_STARTS.with_borrow_mut(|poll_starts| {
+                        poll_starts.insert((self.key(), *iid), timestamp.clone())
+                    });
+                }
+                if let Some(threshold) = self.scheduled_not_polled {
+                    let woken_at = self.lock_scheduled().remove(iid);
+                    if let Some(woken_at) = woken_at
+                        && elapsed(&woken_at, timestamp) > threshold
+                    {
+                        self.fire(Trigger::ScheduledNotPolled(threshold), timestamp);
+                    }
+                }
+            }
+            RecordData::TaskPollEnd { iid } => {
+                if let Some(threshold) = self.long_poll {
+                    let started_at = POLL_STARTS
+                        .with_borrow_mut(|poll_starts| poll_starts.remove(&(self.key(), *iid)));
+                    if let Some(started_at) = started_at
+                        && elapsed(&started_at, timestamp) > threshold
+                    {
+                        self.fire(Trigger::LongPoll(threshold), timestamp);
+                    }
+                }
+            }
+            RecordData::WakerWake { waker } | RecordData::WakerWakeByRef { waker }
+                if self.scheduled_not_polled.is_some() =>
+            {
+                // Only the first wake counts, the task is scheduled from that moment.
+                self.lock_scheduled()
+                    .entry(waker.task_iid)
+                    .or_insert_with(|| timestamp.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Check for tasks which are still waiting to be polled and write any capture which is due.
+    ///
+    /// Returns the time until the pending capture is due, if there is one.
+    pub(crate) fn run_pending(&self, writer: &ChunkedWriter) -> Option<Duration> {
+        let now = AbsTimestamp::now();
+        self.check_scheduled(&now);
+
+        let pending = match self.take_due_capture(&now) {
+            DueCapture::None => return None,
+            DueCapture::DueIn(due_in) => return Some(due_in),
+            DueCapture::Due(pending) => pending,
+        };
+
+        let path = self.config.captures_dir.join(format!(
+            "capture-{secs}.{micros:06}-{name}.rfr",
+            secs = pending.fired_at.secs,
+            micros = pending.fired_at.subsec_micros(),
+            name = pending.trigger.name(),
+        ));
+        if let Err(err) = writer.dump_to(&path) {
+            eprintln!(
+                "Failed to write capture for trigger ({trigger}) to {path}: {err}",
+                trigger = pending.trigger,
+                path = path.display(),
+            );
+        }
+
+        None
+    }
+
+    /// A task which is never polled won't be caught by `observe`, so we check for them here.
+    fn check_scheduled(&self, now: &AbsTimestamp) {
+        let Some(threshold) = self.scheduled_not_polled else {
+            return;
+        };
+
+        let fired = {
+            let mut scheduled = self.lock_scheduled();
+            let before = scheduled.len();
+            // These tasks are reported now, don't let them fire the trigger again.
+            scheduled.retain(|_, woken_at| elapsed(woken_at, now) <= threshold);
+            scheduled.len() < before
+        };
+        if fired {
+            self.fire(Trigger::ScheduledNotPolled(threshold), now);
+        }
+    }
+
+    /// Take the pending capture if it is due at `now`.
+    fn take_due_capture(&self, now: &AbsTimestamp) -> DueCapture {
+        let mut fire_state = self.lock_fire_state();
+        let Some(pending) = &fire_state.pending_capture else {
+            return DueCapture::None;
+        };
+        let since_fired = elapsed(&pending.fired_at, now);
+        if since_fired < self.config.after {
+            return DueCapture::DueIn(self.config.after - since_fired);
+        }
+
+        match fire_state.pending_capture.take() {
+            Some(pending) => DueCapture::Due(pending),
+            None => DueCapture::None,
+        }
+    }
+
+    fn fire(&self, trigger: Trigger, timestamp: &AbsTimestamp) {
+        let mut fire_state = self.lock_fire_state();
+        if fire_state.pending_capture.is_some() {
+            // The pending capture will already cover this trigger.
+            return;
+        }
+        if let Some(last_fired_at) = &fire_state.last_fired_at
+            && elapsed(last_fired_at, timestamp) < self.config.cooldown
+        {
+            return;
+        }
+
+        fire_state.last_fired_at = Some(timestamp.clone());
+        fire_state.pending_capture = Some(PendingCapture {
+            trigger,
+            fired_at: timestamp.clone(),
+        });
+    }
+
+    fn key(&self) -> usize {
+        ptr::from_ref(self) as usize
+    }
+
+    fn lock_scheduled(&self) -> MutexGuard<'_, HashMap<InstrumentationId, AbsTimestamp>> {
+        self.scheduled.lock().expect("scheduled tasks poisoned")
+    }
+
+    fn lock_fire_state(&self) -> MutexGuard<'_, FireState> {
+        self.fire_state.lock().expect("trigger state poisoned")
+    }
+}
+
+fn elapsed(earlier: &AbsTimestamp, later: &AbsTimestamp) -> Duration {
+    later
+        .as_duration_since_epoch()
+        .saturating_sub(earlier.as_duration_since_epoch())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_millis(millis: u64) -> AbsTimestamp {
+        AbsTimestamp::from(Duration::from_secs(1_700_000_000) + Duration::from_millis(millis))
+    }
+
+    fn iid(id: u64) -> InstrumentationId {
+        InstrumentationId::from(id)
+    }
+
+    fn triggers(trigger: Trigger) -> Triggers {
+        Triggers::new(
+            TriggerConfig::new("captures")
+                .with_trigger(trigger)
+                .with_window(Duration::from_secs(1), Duration::from_secs(1))
+                .with_cooldown(Duration::from_secs(10)),
+        )
+    }
+
+    fn poll(triggers: &Triggers, id: u64, start_millis: u64, end_millis: u64) {
+        triggers.observe(
+            &at_millis(start_millis),
+            &RecordData::TaskPollStart { iid: iid(id) },
+        );
+        triggers.observe(
+            &at_millis(end_millis),
+            &RecordData::TaskPollEnd { iid: iid(id) },
+        );
+    }
+
+    /// Returns the trigger which fired at `fired_millis` after waiting for the capture window.
+    fn take_capture(triggers: &Triggers, fired_millis: u64) -> Option<Trigger> {
+        match triggers.take_due_capture(&at_millis(fired_millis + 1_000)) {
+            DueCapture::Due(pending) => {
+                assert_eq!(pending.fired_at, at_millis(fired_millis));
+                Some(pending.trigger)
+            }
+            DueCapture::None => None,
+            DueCapture::DueIn(due_in) => panic!("capture not due yet, due in {due_in:?}"),
+        }
+    }
+
+    #[test]
+    fn long_poll_threshold() {
+        let trigger = Trigger::LongPoll(Duration::from_millis(100));
+        let triggers = triggers(trigger);
+
+        poll(&triggers, 1, 0, 100);
+        assert!(matches!(
+            triggers.take_due_capture(&at_millis(5_000)),
+            DueCapture::None
+        ));
+
+        poll(&triggers, 1, 200, 301);
+        assert!(matches!(
+            triggers.take_due_capture(&at_millis(301)),
+            DueCapture::DueIn(_)
+        ));
+        assert_eq!(take_capture(&triggers, 301), Some(trigger));
+    }
+
+    #[test]
+    fn long_poll_cooldown() {
+        let trigger = Trigger::LongPoll(Duration::from_millis(100));
+        let triggers = triggers(trigger);
+
+        poll(&triggers, 1, 0, 200);
+        assert_eq!(take_capture(&triggers, 200), Some(trigger));
+
+        // Within the cooldown, further long polls don't fire.
+        poll(&triggers, 1, 2_000, 2_200);
+        poll(&triggers, 2, 9_000, 9_200);
+        assert_eq!(take_capture(&triggers, 9_200), None);
+
+        // After the cooldown, they do.
+        poll(&triggers, 1, 10_000, 10_300);
+        assert_eq!(take_capture(&triggers, 10_300), Some(trigger));
+    }
+
+    #[test]
+    fn live_tasks_fire_on_crossing_threshold() {
+        let trigger = Trigger::LiveTasks(2);
+        let triggers = triggers(trigger);
+
+        for id in 1..=2 {
+            triggers.observe(&at_millis(0), &RecordData::TaskNew { iid: iid(id) });
+        }
+        assert_eq!(take_capture(&triggers, 0), None);
+
+        triggers.observe(&at_millis(100), &RecordData::TaskNew { iid: iid(3) });
+        assert_eq!(take_capture(&triggers, 100), Some(trigger));
+
+        // Staying over the threshold doesn't fire again, even after the cooldown.
+        triggers.observe(&at_millis(20_000), &RecordData::TaskNew { iid: iid(4) });
+        assert_eq!(take_capture(&triggers, 20_000), None);
+
+        // Dropping back to the threshold and crossing it again does.
+        for id in 3..=4 {
+            triggers.observe(&at_millis(21_000), &RecordData::TaskDrop { iid: iid(id) });
+        }
+        triggers.observe(&at_millis(22_000), &RecordData::TaskNew { iid: iid(5) });
+        assert_eq!(take_capture(&triggers, 22_000), Some(trigger));
+    }
+
+    #[test]
+    fn scheduled_not_polled() {
+        let trigger = Trigger::ScheduledNotPolled(Duration::from_millis(100));
+        let triggers = triggers(trigger);
+        let waker = |id| rfr::Waker {
+            task_iid: iid(id),
+            context: None,
+        };
+
+        triggers.observe(&at_millis(0), &RecordData::WakerWake { waker: waker(1) });
+        poll(&triggers, 1, 50, 60);
+        assert_eq!(take_capture(&triggers, 60), None);
+
+        triggers.observe(&at_millis(100), &RecordData::WakerWake { waker: waker(1) });
+        // Later wakes don't move the time the task was scheduled.
+        triggers.observe(
+            &at_millis(150),
+            &RecordData::WakerWakeByRef { waker: waker(1) },
+        );
+        poll(&triggers, 1, 201, 210);
+        assert_eq!(take_capture(&triggers, 201), Some(trigger));
+    }
+
+    #[test]
+    fn scheduled_never_polled() {
+        let trigger = Trigger::ScheduledNotPolled(Duration::from_millis(100));
+        let triggers = triggers(trigger);
+        let waker = rfr::Waker {
+            task_iid: iid(1),
+            context: None,
+        };
+
+        triggers.observe(&at_millis(0), &RecordData::WakerWake { waker });
+        triggers.check_scheduled(&at_millis(50));
+        assert_eq!(take_capture(&triggers, 50), None);
+
+        triggers.check_scheduled(&at_millis(101));
+        assert_eq!(take_capture(&triggers, 101), Some(trigger));
+
+        // The task has been reported, so it doesn't fire again.
+        triggers.check_scheduled(&at_millis(20_000));
+        assert_eq!(take_capture(&triggers, 20_000), None);
+    }
+}
@@ -0,0 +1,569 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use rfr::{
+    Callsite, CallsiteId, Field, FieldName, FieldValue, Location, Parent, PollOp, ResourceKind,
+    StateUpdate, StateUpdateOp,
+    chunked::{self, RecordData, from_path},
+};
+use rfr_subscriber::{RfrChunkedLayer, RfrChunkedLayerBuilder};
+use tempfile::tempdir;
+use tracing::{Event, Subscriber, field, subscriber::with_default};
+use tracing_subscriber::{
+    Layer,
+    layer::{Context, Identity, SubscriberExt},
+    registry::Registry,
+};
+
+/// The callsites, objects and records of a chunked recording, in the order they were read.
+struct Recorded {
+    callsites: Vec<Callsite>,
+    objects: Vec<chunked::Object>,
+    records: Vec<RecordData>,
+}
+
+impl Recorded {
+    fn callsite(&self, callsite_id: CallsiteId) -> &Callsite {
+        self.callsites
+            .iter()
+            .find(|callsite| callsite.callsite_id == callsite_id)
+            .expect("callsite not recorded")
+    }
+
+    fn async_ops(&self) -> Vec<&rfr::AsyncOp> {
+        self.objects
+            .iter()
+            .filter_map(|object| match object {
+                chunked::Object::AsyncOp(async_op) => Some(async_op),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the task with the given task Id.
+    fn task(&self, task_id: u64) -> &rfr::Task {
+        self.objects
+            .iter()
+            .find_map(|object| match object {
+                chunked::Object::Task(task) if task.task_id.as_u64() == task_id => Some(task),
+                _ => None,
+            })
+            .expect("task not recorded")
+    }
+
+    fn resources(&self) -> Vec<&rfr::Resource> {
+        self.objects
+            .iter()
+            .filter_map(|object| match object {
+                chunked::Object::Resource(resource) => Some(resource),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn spans(&self) -> Vec<&rfr::Span> {
+        self.objects
+            .iter()
+            .filter_map(|object| match object {
+                chunked::Object::Span(span) => Some(span),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the span created at the callsite with the given name.
+    fn span(&self, name: &str) -> &rfr::Span {
+        self.spans()
+            .into_iter()
+            .find(|span| callsite_name(self.callsite(span.callsite_id())) == name)
+            .expect("span not recorded")
+    }
+
+    fn events(&self) -> Vec<&rfr::Event> {
+        self.records
+            .iter()
+            .filter_map(|data| match data {
+                RecordData::Event { event } => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Records everything that `f` instruments with an in-memory layer, then dumps and reads it.
+///
+/// The `other` layer is added to the same subscriber, on the inside of the RFR layer.
+fn record<L>(builder: RfrChunkedLayerBuilder, other: L, f: impl FnOnce()) -> Recorded
+where
+    L: Layer<Registry> + Send + Sync + 'static,
+{
+    let layer = builder.build_in_memory(Duration::from_secs(10)).unwrap();
+    let dumper = layer.dumper();
+    let recording_dir = tempdir().unwrap();
+    let recording_path = recording_dir.path().join("recording.rfr");
+
+    with_default(Registry::default().with(other).with(layer), || {
+        f();
+        dumper.dump_to(&recording_path).unwrap();
+    });
+
+    let mut recording = from_path(recording_path.to_str().unwrap().to_owned()).unwrap();
+    let mut recorded = Recorded {
+        callsites: recording.callsites().callsites.clone(),
+        objects: Vec::new(),
+        records: Vec::new(),
+    };
+    for chunk in recording.chunks_lossy().flatten() {
+        for seq_chunk in chunk.seq_chunks() {
+            recorded.objects.extend(seq_chunk.objects.iter().cloned());
+            recorded
+                .records
+                .extend(seq_chunk.records.iter().map(|record| record.data.clone()));
+        }
+    }
+    recorded
+}
+
+fn callsite_name(callsite: &Callsite) -> &str {
+    callsite
+        .const_fields
+        .iter()
+        .find_map(|field| match (&field.name.0[..], &field.value) {
+            ("name", FieldValue::Str(name)) => Some(&name[..]),
+            _ => None,
+        })
+        .expect("callsite has no name")
+}
+
+/// Creates a task span with the fields that Tokio's `runtime.spawn` spans have.
+fn spawn_span(task_id: u64) -> tracing::Span {
+    tracing::trace_span!(
+        target: "tokio::task",
+        "runtime.spawn",
+        kind = "task",
+        task.name = "worker",
+        task.id = task_id,
+    )
+}
+
+fn field(name: &str, value: FieldValue) -> Field {
+    Field {
+        name: FieldName(name.into()),
+        value,
+    }
+}
+
+/// Counts the events it sees.
+#[derive(Clone, Default)]
+struct CountingLayer {
+    events: Arc<AtomicUsize>,
+}
+
+impl<S: Subscriber> Layer<S> for CountingLayer {
+    fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn disabled_categories_are_seen_by_other_layers() {
+    let counting = CountingLayer::default();
+    let events = Arc::clone(&counting.events);
+
+    let recorded = record(
+        RfrChunkedLayer::builder().record_events(false),
+        counting,
+        || {
+            tracing::info!(answer = 42, "not recorded");
+            tracing::info!("not recorded either");
+        },
+    );
+
+    assert_eq!(events.load(Ordering::Relaxed), 2);
+    assert!(recorded.events().is_empty());
+    assert!(recorded.objects.is_empty());
+}
+
+#[test]
+fn generic_spans_are_recorded_with_their_parent() {
+    let recorded = record(RfrChunkedLayer::builder(), Identity::new(), || {
+        let outer = tracing::info_span!("outer", answer = 42_u64, question = "unknown");
+        let _outer_guard = outer.enter();
+        let inner = tracing::info_span!("inner", answer = 42_u64, result = field::Empty);
+        inner.in_scope(|| {});
+        tracing::info_span!(parent: None, "detached").in_scope(|| {});
+    });
+
+    let outer = recorded.span("outer");
+    let split_field_names: Vec<_> = recorded
+        .callsite(outer.callsite_id())
+        .split_field_names
+        .iter()
+        .map(|name| &name.0[..])
+        .collect();
+    assert_eq!(split_field_names, ["answer", "question"]);
+    assert_eq!(outer.parent(), &Parent::Root);
+    assert_eq!(
+        outer.split_field_values(),
+        [FieldValue::U64(42), FieldValue::Str("unknown".into())]
+    );
+    assert!(outer.dynamic_fields().is_empty());
+
+    // Without a value for every split field, the recorded values are all dynamic.
+    let inner = recorded.span("inner");
+    assert_eq!(inner.parent(), &Parent::Explicit { iid: outer.iid() });
+    assert!(inner.split_field_values().is_empty());
+    assert_eq!(
+        inner.dynamic_fields(),
+        [field("answer", FieldValue::U64(42))]
+    );
+
+    assert_eq!(recorded.span("detached").parent(), &Parent::Root);
+
+    let inner_records: Vec<_> = recorded
+        .records
+        .iter()
+        .filter(|data| match data {
+            RecordData::SpanNew { iid }
+            | RecordData::SpanEnter { iid }
+            | RecordData::SpanExit { iid }
+            | RecordData::SpanClose { iid } => iid == &inner.iid(),
+            _ => false,
+        })
+        .collect();
+    assert_eq!(
+        inner_records,
+        [
+            &RecordData::SpanNew { iid: inner.iid() },
+            &RecordData::SpanEnter { iid: inner.iid() },
+            &RecordData::SpanExit { iid: inner.iid() },
+            &RecordData::SpanClose { iid: inner.iid() },
+        ]
+    );
+}
+
+#[test]
+fn generic_events_are_recorded_with_their_parent() {
+    let recorded = record(
+        RfrChunkedLayer::builder().record_tasks(false),
+        Identity::new(),
+        || {
+            tracing::info!(answer = 42_u64, "at the root");
+            let span = tracing::info_span!("outer");
+            let _span_guard = span.enter();
+            tracing::info!(ready = true, result = field::Empty, "in a span");
+            // Task spans aren't recorded, so the event's parent is the generic span.
+            spawn_span(7).in_scope(|| tracing::info!("in a task"));
+        },
+    );
+
+    let outer = recorded.span("outer");
+    let events = recorded.events();
+    assert_eq!(events.len(), 3);
+
+    let split_field_names: Vec<_> = recorded
+        .callsite(events[0].callsite_id)
+        .split_field_names
+        .iter()
+        .map(|name| &name.0[..])
+        .collect();
+    assert_eq!(split_field_names, ["message", "answer"]);
+    assert_eq!(events[0].parent, Parent::Root);
+    assert_eq!(
+        events[0].split_field_values,
+        [FieldValue::Str("at the root".into()), FieldValue::U64(42)]
+    );
+    assert!(events[0].dynamic_fields.is_empty());
+
+    // Without a value for every split field, the recorded values are all dynamic.
+    assert_eq!(events[1].parent, Parent::Explicit { iid: outer.iid() });
+    assert!(events[1].split_field_values.is_empty());
+    assert_eq!(
+        events[1].dynamic_fields,
+        [
+            field("message", FieldValue::Str("in a span".into())),
+            field("ready", FieldValue::Bool(true)),
+        ]
+    );
+
+    assert_eq!(events[2].parent, Parent::Explicit { iid: outer.iid() });
+    assert!(
+        recorded
+            .objects
+            .iter()
+            .all(|object| matches!(object, chunked::Object::Span(_)))
+    );
+}
+
+#[test]
+fn resource_spans_are_recorded_as_resources() {
+    let recorded = record(RfrChunkedLayer::builder(), Identity::new(), || {
+        tracing::trace_span!(
+            target: "tokio::time::sleep",
+            "runtime.resource",
+            concrete_type = "Sleep",
+            kind = "timer",
+            loc.file = "src/main.rs",
+            loc.line = 12_u32,
+            loc.col = 9_u32,
+        )
+        .in_scope(|| {});
+        tracing::trace_span!(
+            target: "tokio::sync::semaphore",
+            "runtime.resource",
+            concrete_type = "Semaphore",
+            kind = "Sync",
+            is_internal = true,
+        )
+        .in_scope(|| {});
+        tracing::trace_span!(
+            target: "tokio::sync::oneshot",
+            "runtime.resource",
+            concrete_type = "Sender|Receiver",
+            kind = "Sync|Channel",
+        )
+        .in_scope(|| {});
+        // Without a kind, this isn't a resource.
+        tracing::trace_span!("runtime.resource", concrete_type = "Unknown").in_scope(|| {});
+    });
+
+    let resources = recorded.resources();
+    assert_eq!(resources.len(), 3);
+    let resource = |concrete_type: &str| {
+        *resources
+            .iter()
+            .find(|resource| resource.concrete_type == concrete_type)
+            .expect("resource not recorded")
+    };
+
+    let sleep = resource("Sleep");
+    assert_eq!(sleep.kind, ResourceKind::Timer);
+    assert!(!sleep.is_internal);
+    assert_eq!(
+        sleep.location,
+        Some(Location {
+            file: "src/main.rs".into(),
+            line: 12,
+            col: 9,
+        })
+    );
+
+    let semaphore = resource("Semaphore");
+    assert_eq!(semaphore.kind, ResourceKind::Sync);
+    assert!(semaphore.is_internal);
+    assert_eq!(semaphore.location, None);
+
+    assert_eq!(
+        resource("Sender|Receiver").kind,
+        ResourceKind::Other("Sync|Channel".into())
+    );
+    assert!(recorded.spans().is_empty());
+
+    let sleep_records: Vec<_> = recorded
+        .records
+        .iter()
+        .filter(|data| match data {
+            RecordData::ResourceNew { iid } | RecordData::ResourceDrop { iid } => iid == &sleep.iid,
+            _ => false,
+        })
+        .collect();
+    assert_eq!(
+        sleep_records,
+        [
+            &RecordData::ResourceNew { iid: sleep.iid },
+            &RecordData::ResourceDrop { iid: sleep.iid },
+        ]
+    );
+}
+
+#[test]
+fn async_ops_are_linked_to_their_resource_and_task() {
+    let recorded = record(RfrChunkedLayer::builder(), Identity::new(), || {
+        let semaphore = tracing::trace_span!(
+            target: "tokio::sync::semaphore",
+            "runtime.resource",
+            concrete_type = "Semaphore",
+            kind = "Sync",
+        );
+
+        spawn_span(7).in_scope(|| {
+            let acquire = tracing::trace_span!(
+                target: "tokio::sync::semaphore",
+                parent: &semaphore,
+                "runtime.resource.async_op",
+                source = "Semaphore::acquire",
+            );
+            tracing::trace_span!(
+                target: "tokio::sync::semaphore",
+                parent: &acquire,
+                "runtime.resource.async_op.poll",
+            )
+            .in_scope(|| {});
+        });
+
+        // Created outside of a task, so there's no context.
+        tracing::trace_span!(
+            target: "tokio::sync::semaphore",
+            parent: &semaphore,
+            "runtime.resource.async_op",
+            source = "Semaphore::acquire_owned",
+        )
+        .in_scope(|| {});
+
+        // Without a resource, this isn't an async op.
+        tracing::trace_span!("runtime.resource.async_op", source = "Orphan::acquire")
+            .in_scope(|| {});
+    });
+
+    let semaphore = recorded.resources()[0];
+    let task = recorded.task(7);
+    let async_ops = recorded.async_ops();
+    assert_eq!(async_ops.len(), 2);
+    let async_op = |source: &str| {
+        *async_ops
+            .iter()
+            .find(|async_op| async_op.source == source)
+            .expect("async op not recorded")
+    };
+
+    let acquire = async_op("Semaphore::acquire");
+    assert_eq!(acquire.resource_iid, semaphore.iid);
+    assert_eq!(acquire.context, Some(task.iid));
+
+    let acquire_owned = async_op("Semaphore::acquire_owned");
+    assert_eq!(acquire_owned.resource_iid, semaphore.iid);
+    assert_eq!(acquire_owned.context, None);
+
+    let acquire_records: Vec<_> = recorded
+        .records
+        .iter()
+        .filter(|data| match data {
+            RecordData::AsyncOpNew { iid }
+            | RecordData::AsyncOpPollStart { iid }
+            | RecordData::AsyncOpPollEnd { iid }
+            | RecordData::AsyncOpDrop { iid } => iid == &acquire.iid,
+            _ => false,
+        })
+        .collect();
+    assert_eq!(
+        acquire_records,
+        [
+            &RecordData::AsyncOpNew { iid: acquire.iid },
+            &RecordData::AsyncOpPollStart { iid: acquire.iid },
+            &RecordData::AsyncOpPollEnd { iid: acquire.iid },
+            &RecordData::AsyncOpDrop { iid: acquire.iid },
+        ]
+    );
+}
+
+#[test]
+fn poll_ops_and_state_updates_are_recorded() {
+    let recorded = record(RfrChunkedLayer::builder(), Identity::new(), || {
+        let semaphore = tracing::trace_span!(
+            target: "tokio::sync::semaphore",
+            "runtime.resource",
+            concrete_type = "Semaphore",
+            kind = "Sync",
+        );
+        semaphore.in_scope(|| {
+            tracing::trace!(
+                target: "runtime::resource::state_update",
+                permits = 5_u64,
+                permits.op = "override",
+            );
+            tracing::trace!(
+                target: "runtime::resource::state_update",
+                duration = 100_u64,
+                duration.unit = "ms",
+            );
+        });
+
+        spawn_span(7).in_scope(|| {
+            let acquire = tracing::trace_span!(
+                target: "tokio::sync::semaphore",
+                parent: &semaphore,
+                "runtime.resource.async_op",
+                source = "Semaphore::acquire",
+            );
+            semaphore.in_scope(|| {
+                acquire.in_scope(|| {
+                    tracing::trace!(
+                        target: "runtime::resource::poll_op",
+                        op_name = "poll_acquire",
+                        is_ready = false,
+                    );
+                    // The attribute and the op field don't share a prefix.
+                    tracing::trace!(
+                        target: "runtime::resource::async_op::state_update",
+                        permits_obtained = 2_u64,
+                        permits.op = "add",
+                    );
+                });
+            });
+        });
+
+        // Outside of a resource, there's nothing to update.
+        tracing::trace!(target: "runtime::resource::state_update", permits = 1_u64);
+    });
+
+    let semaphore = recorded.resources()[0];
+    let acquire = recorded.async_ops()[0];
+    let task = recorded.task(7);
+
+    let mut resource_updates = Vec::new();
+    let mut async_op_updates = Vec::new();
+    let mut poll_ops = Vec::new();
+    for data in &recorded.records {
+        match data {
+            RecordData::ResourceStateUpdate { update } => resource_updates.push(update),
+            RecordData::AsyncOpStateUpdate { update } => async_op_updates.push(update),
+            RecordData::PollOp { poll_op } => poll_ops.push(poll_op),
+            _ => {}
+        }
+    }
+
+    assert_eq!(
+        resource_updates,
+        [
+            &StateUpdate {
+                iid: semaphore.iid,
+                attribute: FieldName("permits".into()),
+                value: FieldValue::U64(5),
+                unit: None,
+                op: Some(StateUpdateOp::Override),
+            },
+            &StateUpdate {
+                iid: semaphore.iid,
+                attribute: FieldName("duration".into()),
+                value: FieldValue::U64(100),
+                unit: Some("ms".into()),
+                op: None,
+            },
+        ]
+    );
+    assert_eq!(
+        async_op_updates,
+        [&StateUpdate {
+            iid: acquire.iid,
+            attribute: FieldName("permits_obtained".into()),
+            value: FieldValue::U64(2),
+            unit: None,
+            op: Some(StateUpdateOp::Add),
+        }]
+    );
+    assert_eq!(
+        poll_ops,
+        [&PollOp {
+            resource_iid: semaphore.iid,
+            async_op_iid: Some(acquire.iid),
+            op_name: "poll_acquire".into(),
+            is_ready: false,
+            context: Some(task.iid),
+        }]
+    );
+}
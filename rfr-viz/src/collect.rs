@@ -2,7 +2,7 @@ use std::{collections::HashMap, convert::identity, fmt, ops::Add, time::Duration
 
 use rfr::{
     AbsTimestamp, InstrumentationId, Task,
-    chunked::{self, RecordData},
+    chunked::{self, RecordData, SeqId},
     streamed,
 };
 
@@ -34,7 +34,7 @@ pub(crate) struct WinTimeHandle {
 }
 
 fn duration_from_abs_timestamp(abs_time: &AbsTimestamp) -> Duration {
-    Duration::new(abs_time.secs, abs_time.subsec_micros * 1000)
+    abs_time.as_duration_since_epoch()
 }
 
 impl WinTimeHandle {
@@ -46,7 +46,7 @@ impl WinTimeHandle {
 
     pub(crate) fn window_time(&self, abs_time: &AbsTimestamp) -> WinTimestamp {
         let start_time = duration_from_abs_timestamp(&self.start_time);
-        let duration = Duration::new(abs_time.secs, abs_time.subsec_micros * 1000);
+        let duration = abs_time.as_duration_since_epoch();
         let window_micros = duration.saturating_sub(start_time).as_micros();
         debug_assert!(
             window_micros < u64::MAX as u128,
@@ -128,6 +128,10 @@ impl TaskRecords {
 #[derive(Debug)]
 pub(crate) struct Record {
     pub(crate) timestamp: AbsTimestamp,
+    /// The sequence (thread) that the record was recorded on.
+    ///
+    /// Streamed recordings don't have sequences, so this will always be `None` for them.
+    pub(crate) seq_id: Option<SeqId>,
     pub(crate) data: chunked::RecordData,
 }
 
@@ -200,6 +204,9 @@ pub(crate) fn chunked_recording_info(path: String) -> Option<RecordingInfo> {
             for object in &seq_chunk.objects {
                 println!("    - {object:?}");
             }
+            if !seq_chunk.unresolved_iids.is_empty() {
+                println!("  - Unresolved: {:?}", seq_chunk.unresolved_iids);
+            }
             println!("  - Records:");
             for records in &seq_chunk.records {
                 println!("    - {records:?}");
@@ -267,9 +274,17 @@ pub(crate) fn collect_into_tasks_from_chunked_recording(
 
                 let record = Record {
                     timestamp: chunk.abs_timestamp(&record.meta.timestamp),
+                    seq_id: Some(seq_chunk.header.seq_id),
                     data: record.data.clone(),
                 };
 
+                if seq_chunk.unresolved_iids.contains(task_iid) {
+                    // The task's object wasn't available when the record was written, show the
+                    // record on an unknown task instead of losing it.
+                    tasks
+                        .entry(*task_iid)
+                        .or_insert_with(|| TaskRecords::new(Task::unknown(*task_iid)));
+                }
                 tasks
                     .entry(*task_iid)
                     .and_modify(|r| r.records.push(record));
@@ -346,6 +361,7 @@ pub(crate) fn collect_into_tasks_from_streaming_records(
             };
             let record = Record {
                 timestamp: record.meta.timestamp.clone(),
+                seq_id: None,
                 data: record_data,
             };
             tasks.entry(iid).and_modify(|r| r.records.push(record));
@@ -363,6 +379,8 @@ pub(crate) struct TaskRow {
     pub(crate) last_state: Option<TaskState>,
     pub(crate) spawn: Option<SpawnRecord>,
     pub(crate) wakings: Vec<WakeRecord>,
+    /// The number of times that the task was polled on a different thread to its previous poll.
+    pub(crate) migrations: u64,
 }
 
 impl TaskRow {
@@ -530,6 +548,8 @@ pub(crate) fn collect_into_rows(
         let mut task_records = Vec::new();
         let mut wake_records = Vec::new();
         let mut spawn_record = None;
+        let mut prev_poll_seq_id = None;
+        let mut migrations = 0;
         for rec in records {
             let ts = task_time_handle.task_time(&win_time_handle.window_time(&rec.timestamp));
 
@@ -547,10 +567,18 @@ pub(crate) fn collect_into_rows(
                         kind: TaskRecordKind::New,
                     });
                 }
-                RecordData::TaskPollStart { .. } => task_records.push(TaskRecord {
-                    ts,
-                    kind: TaskRecordKind::PollStart,
-                }),
+                RecordData::TaskPollStart { .. } => {
+                    if let Some(seq_id) = rec.seq_id {
+                        if prev_poll_seq_id.is_some_and(|prev| prev != seq_id) {
+                            migrations += 1;
+                        }
+                        prev_poll_seq_id = Some(seq_id);
+                    }
+                    task_records.push(TaskRecord {
+                        ts,
+                        kind: TaskRecordKind::PollStart,
+                    });
+                }
                 RecordData::TaskPollEnd { .. } => task_records.push(TaskRecord {
                     ts,
                     kind: TaskRecordKind::PollEnd,
@@ -708,6 +736,7 @@ pub(crate) fn collect_into_rows(
             last_state,
             spawn: spawn_record,
             wakings: wake_records,
+            migrations,
         });
     }
 
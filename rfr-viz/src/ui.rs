@@ -366,13 +366,17 @@ fn task_label(ui: &mut egui::Ui, task_row: &TaskRow) -> egui::Response {
 
         let visuals = ui.style().interact_selectable(&response, false);
 
-        let name = match task_row.task.task_kind {
-            TaskKind::BlockOn => "block_on",
-            TaskKind::Blocking if task_row.task.task_name.is_empty() => "Blocking",
-            _ => task_row.task.task_name.as_str(),
+        let task = &task_row.task;
+        let name = match (&task.task_kind, &task.location) {
+            (TaskKind::BlockOn, _) => "block_on".to_string(),
+            _ if !task.task_name.is_empty() => task.task_name.clone(),
+            // Unnamed tasks are labelled by where they were spawned.
+            (_, Some(location)) => location.to_string(),
+            (TaskKind::Blocking, None) => "Blocking".to_string(),
+            (_, None) => String::new(),
         };
         let mut layout_job = egui::text::LayoutJob::simple_singleline(
-            name.to_string(),
+            name,
             egui::FontId::proportional(14.),
             visuals.fg_stroke.color,
         );
@@ -423,7 +427,7 @@ fn task_label(ui: &mut egui::Ui, task_row: &TaskRow) -> egui::Response {
         ui.painter().add(text_shape);
     }
 
-    response
+    response.on_hover_text(format!("Migrations: {}", task_row.migrations))
 }
 
 fn spawn_line(ui: &mut egui::Ui, cursor: egui::Pos2, state: &State, row: &TaskRow) {
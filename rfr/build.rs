@@ -0,0 +1,14 @@
+use std::{env, process::Command};
+
+fn main() {
+    // The Rust version is stored in the metadata of each recording.
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    if let Ok(output) = Command::new(rustc).arg("--version").output()
+        && output.status.success()
+        && let Ok(version) = String::from_utf8(output.stdout)
+    {
+        println!("cargo:rustc-env=RFR_RUST_VERSION={}", version.trim());
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
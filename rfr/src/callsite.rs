@@ -31,8 +31,92 @@ impl From<u64> for CallsiteId {
 }
 
 impl CallsiteId {
+    /// Derive a stable callsite Id from the static description of a callsite.
+    ///
+    /// The Id only depends on the arguments, so the same callsite will have the same Id between
+    /// runs and builds of the instrumented application, which allows callsites to be compared
+    /// across recordings. The Id is the 64-bit FNV-1a hash of the arguments, see the file format
+    /// documentation for details.
+    pub fn from_static_parts<'a>(
+        name: &str,
+        target: &str,
+        module_path: Option<&str>,
+        file: Option<&str>,
+        line: Option<u32>,
+        field_names: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let mut hasher = StableHasher::new();
+        hasher.write_str(name);
+        hasher.write_str(target);
+        hasher.write_option(module_path, StableHasher::write_str);
+        hasher.write_option(file, StableHasher::write_str);
+        hasher.write_option(line, |hasher, line| hasher.write_u64(line as u64));
+        for field_name in field_names {
+            hasher.write_str(field_name);
+        }
+
+        Self(hasher.finish())
+    }
+
+    /// Derive the next candidate Id for a callsite whose Id collides with a different callsite.
+    ///
+    /// The candidate is the FNV-1a hash of this Id, so resolving the same collision always gives
+    /// the same Id.
+    pub fn rehash(&self) -> Self {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.0);
+
+        Self(hasher.finish())
+    }
+
     /// The `u64` representation of the callsite Id.
     pub fn as_u64(&self) -> u64 {
         self.0
     }
 }
+
+/// A 64-bit FNV-1a hasher.
+///
+/// The hashers in `std` don't guarantee that their output is stable between releases, so we use
+/// our own.
+struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Strings are prefixed with their length so that adjacent strings can't run into each other.
+    fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write_bytes(value.as_bytes());
+    }
+
+    fn write_option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        match value {
+            Some(value) => {
+                self.write_bytes(&[1]);
+                write(self, value);
+            }
+            None => self.write_bytes(&[0]),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -52,6 +52,10 @@ impl ChunkedCallsites {
     //
     /// This method will attempt to load the contents of a chunked recording callsites file and
     /// return a [`ChunkedCallsites`] object.
+    ///
+    /// Callsites are appended to the file while the recording is being written, so if the file
+    /// ends part way through a callsite (e.g. because the instrumented application was killed),
+    /// the incomplete callsite is ignored and the callsites before it are returned.
     pub fn try_from_io(reader: impl io::Read) -> Result<Self, CallsitesTryFromIoError> {
         let mut reader = reader;
 
@@ -77,8 +81,12 @@ impl ChunkedCallsites {
                 break;
             }
 
-            let (callsite, rem_bytes): (Callsite, _) = postcard::take_from_bytes(bytes)
-                .map_err(|error| CallsitesTryFromIoError::CallsiteInvalid { idx, error })?;
+            let (callsite, rem_bytes): (Callsite, _) = match postcard::take_from_bytes(bytes) {
+                Ok(result) => result,
+                // The file was truncated while this callsite was being written.
+                Err(postcard::Error::DeserializeUnexpectedEnd) => break,
+                Err(error) => return Err(CallsitesTryFromIoError::CallsiteInvalid { idx, error }),
+            };
             bytes = rem_bytes;
             callsites.push(callsite);
         }
@@ -151,11 +159,18 @@ where
 
     /// Push a callsite to be written during the next [`flush`].
     ///
-    /// The list of existing callsites will be checked for duplicates
+    /// The list of existing callsites will be checked for duplicates and for different callsites
+    /// which have the same [`CallsiteId`].
+    ///
+    /// [`CallsiteId`]: crate::CallsiteId
     pub fn push_callsite(&mut self, callsite: Callsite) -> PushCallsiteResult {
         for existing in &self.chunked_callsites.callsites {
             if existing.callsite_id == callsite.callsite_id {
-                return PushCallsiteResult::Duplicate;
+                if *existing == callsite {
+                    return PushCallsiteResult::Duplicate;
+                } else {
+                    return PushCallsiteResult::Collision;
+                }
             }
         }
         self.chunked_callsites.callsites.push(callsite);
@@ -197,6 +212,9 @@ pub enum PushCallsiteResult {
 
     /// A duplicate callsite (by `CallsiteId`) was found, the callsite wasn't pushed
     Duplicate,
+
+    /// A different callsite with the same `CallsiteId` was found, the callsite wasn't pushed
+    Collision,
 }
 
 /// An error that occurred while flushing callsites to writer.
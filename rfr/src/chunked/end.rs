@@ -0,0 +1,111 @@
+//! Chunked recording end marker
+//!
+//! The end marker is written when a chunked recording is finished. A recording without an end
+//! marker was either still being written or ended abruptly.
+//!
+//! See the [`ChunkedEnd`] struct for details of the contents.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AbsTimestamp, FormatIdentifier, FormatVariant, chunked::WriterStats,
+    identifier::ReadFormatIdentifierError,
+};
+
+/// The format identifier for the End file
+pub fn version() -> FormatIdentifier {
+    FormatIdentifier {
+        variant: FormatVariant::RfrChunkedEnd,
+        major: 0,
+        minor: 0,
+        patch: 1,
+    }
+}
+
+/// End file contents
+///
+/// This struct can be used to serialize and deserialize the chunked recording end file which is
+/// stored at `<chunked-recording.rfr>/end.rfr`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChunkedEnd {
+    /// Format identifier for the end file, the variant should be `rfr-ce`.
+    pub format_identifier: FormatIdentifier,
+
+    /// End file header.
+    pub header: ChunkedEndHeader,
+}
+
+impl ChunkedEnd {
+    /// Create new end file contents with the writer's final statistics.
+    ///
+    /// The end time will be set to the current time. A recording is `clean` if all of its chunks
+    /// were written successfully.
+    pub fn new(stats: WriterStats, clean: bool) -> Self {
+        Self {
+            format_identifier: version(),
+            header: ChunkedEndHeader {
+                end_time: AbsTimestamp::now(),
+                clean,
+                stats,
+            },
+        }
+    }
+
+    /// Read from a recording end file.
+    pub fn try_from_io(reader: impl io::Read) -> Result<Self, EndTryFromIoError> {
+        let mut reader = reader;
+
+        let format_identifier = FormatIdentifier::try_from_io(&mut reader)
+            .map_err(EndTryFromIoError::InvalidFormatIdentifier)?;
+
+        let current_version = version();
+        if !current_version.can_read_version(&format_identifier) {
+            return Err(EndTryFromIoError::IncompatibleFormat(format_identifier));
+        }
+
+        let mut buffer = Vec::new();
+        let _size = reader
+            .read_to_end(&mut buffer)
+            .map_err(EndTryFromIoError::ReadFileFailed)?;
+
+        let header: ChunkedEndHeader =
+            postcard::from_bytes(buffer.as_slice()).map_err(EndTryFromIoError::FileInvalid)?;
+
+        Ok(ChunkedEnd {
+            format_identifier,
+            header,
+        })
+    }
+}
+
+/// An error returned when attempting to read a recording end file.
+#[derive(Debug)]
+pub enum EndTryFromIoError {
+    /// An underlying IO error when reading the file.
+    ReadFileFailed(io::Error),
+    /// The format identifier at the beginning of the file is malformed.
+    InvalidFormatIdentifier(ReadFormatIdentifierError),
+    /// The end file is written in an incompatible format.
+    IncompatibleFormat(FormatIdentifier),
+    /// The file is not a valid End object serialized to [Postcard].
+    ///
+    /// [Postcard]: crate@postcard
+    FileInvalid(postcard::Error),
+}
+
+/// Header for the chunked recording end file.
+///
+/// See [`ChunkedEnd`] for more details and usage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChunkedEndHeader {
+    /// The time that this recording was finished
+    pub end_time: AbsTimestamp,
+
+    /// Whether all the chunks in the recording were written successfully
+    pub clean: bool,
+
+    /// The statistics of the writer when the recording was finished
+    pub stats: WriterStats,
+}
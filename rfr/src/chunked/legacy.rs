@@ -0,0 +1,198 @@
+//! Reading chunked recordings written with earlier versions of the formats.
+//!
+//! The versions of the chunk format which can still be read are the variants of [`ChunkFormat`].
+//! Everything which is read is converted to the current types.
+
+use std::{collections::BTreeMap, io};
+
+use serde::Deserialize;
+
+use crate::{
+    AbsTimestamp, FormatIdentifier, FormatVariant, Span,
+    chunked::{
+        AbsTimestampSecs, ChunkHeader, ChunkInterval, ChunkTimestamp, ChunkedMetaHeader, Object,
+        ProcessInfo, Record, RuntimeInfo, SeqChunk, SeqChunkHeader, SeqId, SeqThread,
+        current_software_version,
+    },
+    legacy::{TaskWithoutLocation, abs_timestamp_from_micros},
+};
+
+/// A version of the chunk format (`rfr-c`) which can be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChunkFormat {
+    /// `rfr-c/0.0.2`, timestamps have microsecond precision and there are no clock adjustments,
+    /// sequence threads, unresolved ids, or resource objects.
+    V0_0_2,
+    /// The current version.
+    Current,
+}
+
+impl ChunkFormat {
+    /// Returns the chunk format for the format identifier, if it can be read.
+    pub(crate) fn from_identifier(identifier: &FormatIdentifier) -> Option<Self> {
+        if current_software_version().can_read_version(identifier) {
+            return Some(Self::Current);
+        }
+
+        let v0_0_2 = FormatIdentifier {
+            variant: FormatVariant::RfrChunked,
+            major: 0,
+            minor: 0,
+            patch: 2,
+        };
+        (identifier == &v0_0_2).then_some(Self::V0_0_2)
+    }
+
+    /// Deserialize a chunk header in this format, converting it to the current types.
+    pub(crate) fn header_from_io<'a, R>(
+        self,
+        io: (R, &'a mut [u8]),
+    ) -> postcard::Result<(ChunkHeader, (R, &'a mut [u8]))>
+    where
+        R: io::Read + 'a,
+    {
+        match self {
+            Self::Current => postcard::from_io(io),
+            Self::V0_0_2 => {
+                let (header, rest): (ChunkHeaderV0_0_2, _) = postcard::from_io(io)?;
+                let interval = header.interval;
+                Ok((
+                    ChunkHeader {
+                        interval: ChunkInterval {
+                            base_time: interval.base_time,
+                            start_time: chunk_timestamp_from_micros(interval.start_time),
+                            end_time: chunk_timestamp_from_micros(interval.end_time),
+                        },
+                        earliest_timestamp: chunk_timestamp_from_micros(header.earliest_timestamp),
+                        latest_timestamp: chunk_timestamp_from_micros(header.latest_timestamp),
+                        clock_adjustments: Vec::new(),
+                    },
+                    rest,
+                ))
+            }
+        }
+    }
+
+    /// Deserialize a sequence chunk in this format, converting it to the current types.
+    ///
+    /// The `base_time` is the base time of the chunk the sequence chunk belongs to.
+    pub(crate) fn seq_chunk_from_io<'a, R>(
+        self,
+        base_time: AbsTimestampSecs,
+        io: (R, &'a mut [u8]),
+    ) -> postcard::Result<(SeqChunk, (R, &'a mut [u8]))>
+    where
+        R: io::Read + 'a,
+    {
+        match self {
+            Self::Current => postcard::from_io(io),
+            Self::V0_0_2 => {
+                let (seq_chunk, rest): (SeqChunkV0_0_2, _) = postcard::from_io(io)?;
+                Ok((seq_chunk.into_seq_chunk(base_time), rest))
+            }
+        }
+    }
+}
+
+fn chunk_timestamp_from_micros(timestamp: ChunkTimestamp) -> ChunkTimestamp {
+    ChunkTimestamp::new(timestamp.nanos * 1_000)
+}
+
+/// A chunk header from `rfr-c/0.0.2`.
+#[derive(Debug, Deserialize)]
+struct ChunkHeaderV0_0_2 {
+    interval: ChunkInterval,
+    earliest_timestamp: ChunkTimestamp,
+    latest_timestamp: ChunkTimestamp,
+}
+
+/// A sequence chunk header from `rfr-c/0.0.2`.
+#[derive(Debug, Deserialize)]
+struct SeqChunkHeaderV0_0_2 {
+    seq_id: SeqId,
+    earliest_timestamp: ChunkTimestamp,
+    latest_timestamp: ChunkTimestamp,
+}
+
+/// A sequence chunk from `rfr-c/0.0.2`.
+///
+/// The record data variants from `rfr-c/0.0.2` are the first variants of the current record data,
+/// so the records are read with the current types.
+#[derive(Debug, Deserialize)]
+struct SeqChunkV0_0_2 {
+    header: SeqChunkHeaderV0_0_2,
+    objects: Vec<ObjectV0_0_2>,
+    records: Vec<Record>,
+}
+
+impl SeqChunkV0_0_2 {
+    fn into_seq_chunk(self, base_time: AbsTimestampSecs) -> SeqChunk {
+        let earliest_timestamp = chunk_timestamp_from_micros(self.header.earliest_timestamp);
+        let header = SeqChunkHeader {
+            seq_id: self.header.seq_id,
+            earliest_timestamp,
+            latest_timestamp: chunk_timestamp_from_micros(self.header.latest_timestamp),
+            // The thread wasn't recorded, the earliest record is the best we have.
+            thread: SeqThread {
+                name: None,
+                os_thread_id: None,
+                first_seen: earliest_timestamp.to_abs_timestamp(base_time),
+            },
+        };
+        let records = self
+            .records
+            .into_iter()
+            .map(|mut record| {
+                record.meta.timestamp = chunk_timestamp_from_micros(record.meta.timestamp);
+                record
+            })
+            .collect();
+
+        SeqChunk {
+            header,
+            objects: self.objects.into_iter().map(Object::from).collect(),
+            // Records whose objects weren't available were dropped by the writer.
+            unresolved_iids: Vec::new(),
+            records,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+enum ObjectV0_0_2 {
+    Span(Span),
+    Task(TaskWithoutLocation),
+}
+
+impl From<ObjectV0_0_2> for Object {
+    fn from(value: ObjectV0_0_2) -> Self {
+        match value {
+            ObjectV0_0_2::Span(span) => Self::Span(span),
+            ObjectV0_0_2::Task(task) => Self::Task(task.into()),
+        }
+    }
+}
+
+/// A meta file header from `rfr-cm/0.0.1`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChunkedMetaHeaderV0_0_1 {
+    created_time: AbsTimestamp,
+    format_identifiers: Vec<FormatIdentifier>,
+}
+
+impl From<ChunkedMetaHeaderV0_0_1> for ChunkedMetaHeader {
+    fn from(value: ChunkedMetaHeaderV0_0_1) -> Self {
+        let created_time = abs_timestamp_from_micros(value.created_time);
+        Self {
+            // Timestamps were taken from the wall clock, so there was no anchor. The created time
+            // is the closest to one.
+            clock_anchor: created_time.clone(),
+            created_time,
+            format_identifiers: value.format_identifiers,
+            // The process and runtime weren't recorded.
+            process: ProcessInfo::default(),
+            runtime: RuntimeInfo::default(),
+            labels: BTreeMap::new(),
+        }
+    }
+}
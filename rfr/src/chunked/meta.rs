@@ -1,14 +1,18 @@
 //! Chunked recording metadata
 //!
-//! The recording metadata contains configuration for a chunked recording.
+//! The recording metadata contains configuration for a chunked recording, together with
+//! information about the process which was recorded.
 //!
 //! See the [`ChunkedMeta`] struct for details of the contents.
 
-use std::io;
+use std::{collections::BTreeMap, env, fs, io, process};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{AbsTimestamp, FormatIdentifier, FormatVariant, identifier::ReadFormatIdentifierError};
+use crate::{
+    AbsTimestamp, FormatIdentifier, FormatVariant, chunked::legacy::ChunkedMetaHeaderV0_0_1,
+    identifier::ReadFormatIdentifierError,
+};
 
 /// The format identifier for the Meta file
 pub fn version() -> FormatIdentifier {
@@ -16,7 +20,7 @@ pub fn version() -> FormatIdentifier {
         variant: FormatVariant::RfrChunkedMeta,
         major: 0,
         minor: 0,
-        patch: 1,
+        patch: 2,
     }
 }
 
@@ -32,6 +36,9 @@ pub fn version() -> FormatIdentifier {
 /// There is also a list of format identifiers which may be used in the recording. Software that is
 /// going to read a recording can check that it is able to read all parts of the recording before
 /// beginning.
+///
+/// Finally, the metadata describes the process and runtime which were recorded, together with any
+/// labels supplied by the user, so that recordings from different hosts can be told apart.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChunkedMeta {
     /// Format identifier for the meta file, the variant should be `rfr-cm`.
@@ -44,7 +51,8 @@ pub struct ChunkedMeta {
 impl ChunkedMeta {
     /// Create new meta file contents with the provided format identifiers.
     ///
-    /// The creation time will be set to the current time.
+    /// The creation time will be set to the current time and the process and runtime information
+    /// will be taken from the current process. There are no labels.
     ///
     /// # Panics
     ///
@@ -59,7 +67,11 @@ impl ChunkedMeta {
             format_identifier: version(),
             header: ChunkedMetaHeader {
                 created_time: AbsTimestamp::now(),
+                clock_anchor: AbsTimestamp::clock_anchor(),
                 format_identifiers,
+                process: ProcessInfo::current(),
+                runtime: RuntimeInfo::current(),
+                labels: BTreeMap::new(),
             },
         }
     }
@@ -75,7 +87,14 @@ impl ChunkedMeta {
             .map_err(MetaTryFromIoError::InvalidFormatIdentifier)?;
 
         let current_version = version();
-        if !current_version.can_read_version(&format_identifier) {
+        // Version 0.0.1 has no clock anchor or process information and its created time is stored
+        // in microseconds, it is converted as it is read.
+        let is_v0_0_1 = format_identifier
+            == FormatIdentifier {
+                patch: 1,
+                ..current_version.clone()
+            };
+        if !is_v0_0_1 && !current_version.can_read_version(&format_identifier) {
             return Err(MetaTryFromIoError::IncompatibleFormat(format_identifier));
         }
 
@@ -84,8 +103,12 @@ impl ChunkedMeta {
             .read_to_end(&mut buffer)
             .map_err(MetaTryFromIoError::ReadFileFailed)?;
 
-        let header: ChunkedMetaHeader =
-            postcard::from_bytes(buffer.as_slice()).map_err(MetaTryFromIoError::FileInvalid)?;
+        let header: ChunkedMetaHeader = if is_v0_0_1 {
+            postcard::from_bytes::<ChunkedMetaHeaderV0_0_1>(buffer.as_slice()).map(Into::into)
+        } else {
+            postcard::from_bytes(buffer.as_slice())
+        }
+        .map_err(MetaTryFromIoError::FileInvalid)?;
 
         // TODO(hds): We should check that the necessary format identifiers are present. Right now,
         // that means `rfr-c`.
@@ -126,8 +149,101 @@ pub struct ChunkedMetaHeader {
     /// The time that this recording was created
     pub created_time: AbsTimestamp,
 
+    /// The wall clock time at which the recording clock was anchored
+    ///
+    /// All timestamps in the recording are measured with a monotonic clock, starting from this
+    /// time. See [`AbsTimestamp::now`] for details.
+    pub clock_anchor: AbsTimestamp,
+
     /// All the format identifiers used in this chunked recording
     ///
     /// Only one format identifier for each variant should be included.
     pub format_identifiers: Vec<FormatIdentifier>,
+
+    /// The process which was recorded
+    pub process: ProcessInfo,
+
+    /// The software used to make the recording and the runtime which was recorded
+    pub runtime: RuntimeInfo,
+
+    /// Arbitrary key/value labels supplied by the user
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Information about the process which was recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProcessInfo {
+    /// The OS process id
+    pub pid: u32,
+
+    /// The path to the executable, if it could be determined
+    pub executable: Option<String>,
+
+    /// The command line arguments, including the program name
+    pub args: Vec<String>,
+
+    /// The name of the host, if it could be determined
+    pub hostname: Option<String>,
+}
+
+impl ProcessInfo {
+    /// Returns the information for the current process.
+    pub fn current() -> Self {
+        Self {
+            pid: process::id(),
+            executable: env::current_exe()
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned()),
+            args: env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            hostname: hostname(),
+        }
+    }
+
+    /// The file name of the executable, if it is known.
+    pub fn executable_name(&self) -> Option<&str> {
+        let executable = self.executable.as_deref()?;
+        executable.rsplit(['/', '\\']).next()
+    }
+}
+
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+}
+
+/// Information about the software used to make a recording and the runtime which was recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RuntimeInfo {
+    /// The version of the `rfr` crate which wrote the recording
+    pub rfr_version: String,
+
+    /// The version of the Rust compiler, as output by `rustc --version`
+    pub rust_version: Option<String>,
+
+    /// The version of Tokio, if it was supplied
+    pub tokio_version: Option<String>,
+
+    /// The number of runtime worker threads, if it was supplied
+    pub worker_threads: Option<u64>,
+}
+
+impl RuntimeInfo {
+    /// Returns the information for the current build.
+    ///
+    /// The Tokio version and worker thread count are not known to `rfr`, so they aren't set.
+    pub fn current() -> Self {
+        Self {
+            rfr_version: env!("CARGO_PKG_VERSION").to_owned(),
+            rust_version: option_env!("RFR_RUST_VERSION").map(ToOwned::to_owned),
+            tokio_version: None,
+            worker_threads: None,
+        }
+    }
 }
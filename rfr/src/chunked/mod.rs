@@ -1,27 +1,41 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{AbsTimestamp, FormatIdentifier, FormatVariant, Span, Task};
+use crate::{AbsTimestamp, AsyncOp, FormatIdentifier, FormatVariant, Resource, Span, Task};
 
 mod callsite;
+mod end;
+mod legacy;
 mod meta;
 mod read;
 mod record;
 mod sequence;
+mod stats;
 mod write;
 
-pub use callsite::{ChunkedCallsites, ChunkedCallsitesWriter, FlushCallsitesError};
-pub use meta::{ChunkedMeta, ChunkedMetaHeader, MetaTryFromIoError};
-pub use read::{Recording, from_path};
+pub use callsite::{
+    CallsitesTryFromIoError, ChunkedCallsites, ChunkedCallsitesWriter, FlushCallsitesError,
+    PushCallsiteResult,
+};
+pub use end::{ChunkedEnd, ChunkedEndHeader, EndTryFromIoError};
+pub use meta::{ChunkedMeta, ChunkedMetaHeader, MetaTryFromIoError, ProcessInfo, RuntimeInfo};
+pub use read::{Recording, RecordingStatus, from_path};
 pub use record::{Meta, Record, RecordData};
-pub use sequence::{SeqChunk, SeqChunkBuffer, SeqChunkHeader, SeqId};
-pub use write::{ChunkedWriter, NewChunkedWriterError, WaitForWriteError, WriteError};
+pub use sequence::{SeqChunk, SeqChunkBuffer, SeqChunkHeader, SeqId, SeqThread};
+pub use stats::WriterStats;
+pub use write::{
+    ChunkedWriter, ChunkedWriterBuilder, DirectoryLayout, DumpError, FinishError,
+    NewChunkedWriterError, Retention, WaitForWriteError, WriteChunksError, WriteError,
+};
+
+/// The file name suffix of a partial chunk, a checkpoint of a chunk which is still being recorded.
+const PARTIAL_CHUNK_SUFFIX: &str = ".partial.rfr";
 
 fn current_software_version() -> FormatIdentifier {
     FormatIdentifier {
         variant: FormatVariant::RfrChunked,
         major: 0,
         minor: 0,
-        patch: 2,
+        patch: 3,
     }
 }
 
@@ -47,24 +61,33 @@ impl AbsTimestampSecs {
     pub fn as_micros(&self) -> u64 {
         self.secs * 1_000_000
     }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.secs * 1_000_000_000
+    }
 }
 
 // A timestamp within a chunk.
 //
 // A chunk timestamp represents the time of a record with respect to the chunk's base time. It is
-// stored as the number of microseconds since the base time. All records within a chunk must occur
+// stored as the number of nanoseconds since the base time. All records within a chunk must occur
 // at the base time or afterwards.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, PartialOrd, Ord, Eq, Deserialize, Serialize)]
 pub struct ChunkTimestamp {
-    /// Microseconds since the chunk's base time
-    pub micros: u64,
+    /// Nanoseconds since the chunk's base time
+    pub nanos: u64,
 }
 
 impl ChunkTimestamp {
-    const ZERO: ChunkTimestamp = ChunkTimestamp { micros: 0 };
+    const ZERO: ChunkTimestamp = ChunkTimestamp { nanos: 0 };
 
-    pub fn new(micros: u64) -> Self {
-        Self { micros }
+    pub fn new(nanos: u64) -> Self {
+        Self { nanos }
+    }
+
+    /// Microseconds since the chunk's base time, truncated.
+    pub fn as_micros(&self) -> u64 {
+        self.nanos / 1_000
     }
 
     /// Create a new chunk timestamp from a base time and an absolute timestamp.
@@ -83,8 +106,8 @@ impl ChunkTimestamp {
     /// ```
     pub fn from_base_and_timestamp(base_time: AbsTimestampSecs, timestamp: &AbsTimestamp) -> Self {
         let secs = timestamp.secs.saturating_sub(base_time.secs);
-        let micros = (secs * 1_000_000) + timestamp.subsec_micros as u64;
-        Self::new(micros)
+        let nanos = (secs * 1_000_000_000) + timestamp.subsec_nanos as u64;
+        Self::new(nanos)
     }
 
     /// Convert to an absolute timestamp, given the base timestamp for this chunk.
@@ -111,6 +134,8 @@ impl ChunkTimestamp {
 pub enum Object {
     Span(Span),
     Task(Task),
+    Resource(Resource),
+    AsyncOp(AsyncOp),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -134,12 +159,12 @@ impl Chunk {
 }
 
 fn abs_timestamp(base_time: AbsTimestampSecs, chunk_timestamp: &ChunkTimestamp) -> AbsTimestamp {
-    let chunk_timestamp_secs = chunk_timestamp.micros / 1_000_000;
-    let chunk_timestamp_subsec_micros = (chunk_timestamp.micros % 1_000_000) as u32;
+    let chunk_timestamp_secs = chunk_timestamp.nanos / 1_000_000_000;
+    let chunk_timestamp_subsec_nanos = (chunk_timestamp.nanos % 1_000_000_000) as u32;
 
     AbsTimestamp {
         secs: base_time.secs + chunk_timestamp_secs,
-        subsec_micros: chunk_timestamp_subsec_micros,
+        subsec_nanos: chunk_timestamp_subsec_nanos,
     }
 }
 
@@ -149,6 +174,10 @@ pub struct ChunkHeader {
 
     pub earliest_timestamp: ChunkTimestamp,
     pub latest_timestamp: ChunkTimestamp,
+
+    /// Adjustments of the wall clock observed up until the end of this chunk, which weren't
+    /// stored in an earlier chunk.
+    pub clock_adjustments: Vec<ClockAdjustment>,
 }
 
 impl ChunkHeader {
@@ -160,10 +189,44 @@ impl ChunkHeader {
             interval,
             earliest_timestamp,
             latest_timestamp,
+            clock_adjustments: Vec::new(),
+        }
+    }
+}
+
+/// An adjustment of the wall clock observed during a recording.
+///
+/// Recording timestamps come from a monotonic clock anchored to the wall clock when the recording
+/// starts (see [`AbsTimestamp::now`]). When the wall clock is adjusted, e.g. by NTP, the two clocks
+/// drift apart. The offset is the wall clock time minus the recording time.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClockAdjustment {
+    /// The recording time at which the adjustment was observed.
+    pub observed_at: AbsTimestamp,
+    /// The offset of the wall clock from the recording clock in microseconds, after the
+    /// adjustment.
+    pub wall_clock_offset_micros: i64,
+}
+
+impl ClockAdjustment {
+    /// Compare the wall clock with the recording clock now.
+    pub fn observe() -> Self {
+        let observed_at = AbsTimestamp::now();
+        let wall_clock = AbsTimestamp::wall_clock_now();
+        let wall_clock_offset_micros =
+            as_signed_micros(&wall_clock) - as_signed_micros(&observed_at);
+
+        Self {
+            observed_at,
+            wall_clock_offset_micros,
         }
     }
 }
 
+fn as_signed_micros(timestamp: &AbsTimestamp) -> i64 {
+    timestamp.secs as i64 * 1_000_000 + timestamp.subsec_micros() as i64
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ChunkInterval {
     base_time: AbsTimestampSecs,
@@ -183,17 +246,15 @@ impl ChunkInterval {
                 ChunkTimestamp::ZERO,
             )
         } else {
+            let subsec_micros = timestamp.subsec_micros() as u64;
             (
                 AbsTimestampSecs::from(timestamp.clone()),
                 // Calculate the start time (offset) based on the period.
-                ChunkTimestamp::new(
-                    (timestamp.subsec_micros - (timestamp.subsec_micros % period_micros as u32))
-                        as u64,
-                ),
+                ChunkTimestamp::new((subsec_micros - (subsec_micros % period_micros)) * 1_000),
             )
         };
 
-        let end_time = ChunkTimestamp::new(start_time.micros + period_micros);
+        let end_time = ChunkTimestamp::new(start_time.nanos + period_micros * 1_000);
 
         Self {
             base_time,
@@ -1,4 +1,5 @@
 use std::{
+    collections::{HashMap, HashSet},
     error, fmt, fs,
     io::{self, SeekFrom},
     path::{Path, PathBuf},
@@ -7,18 +8,43 @@ use std::{
 use walkdir::WalkDir;
 
 use crate::{
-    FormatIdentifier,
+    AbsTimestamp, Callsite, CallsiteId, FormatIdentifier,
     chunked::{
-        Chunk, ChunkHeader, ChunkedMeta, MetaTryFromIoError, SeqChunk, current_software_version,
+        CallsitesTryFromIoError, Chunk, ChunkHeader, ChunkedCallsites, ChunkedEnd, ChunkedMeta,
+        ClockAdjustment, MetaTryFromIoError, PARTIAL_CHUNK_SUFFIX, SeqChunk, legacy::ChunkFormat,
     },
 };
 
 #[derive(Debug)]
 pub struct Recording {
     meta: ChunkedMeta,
+    end: Option<ChunkedEnd>,
+    callsites: ChunkedCallsites,
+    /// The index of each callsite in `callsites`.
+    callsite_indices: HashMap<CallsiteId, usize>,
     chunks: Vec<ChunkLoader>,
 }
 
+/// How a chunked recording ended.
+///
+/// See [`Recording::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordingStatus {
+    /// The recording was finished and all of its chunks were written.
+    Finished { end_time: AbsTimestamp },
+    /// The recording was finished, but some chunks couldn't be written.
+    FinishedWithErrors {
+        end_time: AbsTimestamp,
+        write_errors: u64,
+    },
+    /// The recording has no end file (or it can't be read). It is either still being written, or
+    /// the process being recorded ended abruptly. The latest timestamp recorded in any chunk is included if there is
+    /// one.
+    Unfinished {
+        latest_timestamp: Option<AbsTimestamp>,
+    },
+}
+
 impl Recording {
     pub fn load_all_chunks(&mut self) {
         for chunk_loader in &mut self.chunks {
@@ -30,6 +56,62 @@ impl Recording {
         &self.meta
     }
 
+    /// The contents of the end file, which is only present if the recording was finished.
+    pub fn end(&self) -> Option<&ChunkedEnd> {
+        self.end.as_ref()
+    }
+
+    /// The callsites of all the spans and events in the recording.
+    ///
+    /// If the recording has no callsites file, there will be no callsites.
+    pub fn callsites(&self) -> &ChunkedCallsites {
+        &self.callsites
+    }
+
+    /// Look up a callsite by its Id.
+    pub fn callsite(&self, callsite_id: &CallsiteId) -> Option<&Callsite> {
+        self.callsite_indices
+            .get(callsite_id)
+            .map(|idx| &self.callsites.callsites[*idx])
+    }
+
+    /// Returns the wall clock adjustments observed during the recording, in the order they were
+    /// observed.
+    ///
+    /// The chunk headers are loaded to find the adjustments.
+    pub fn clock_adjustments(&mut self) -> Vec<ClockAdjustment> {
+        self.chunk_headers_lossy()
+            .flatten()
+            .flat_map(|header| header.clock_adjustments.iter().cloned())
+            .collect()
+    }
+
+    /// Returns how this recording ended.
+    ///
+    /// For an unfinished recording, the chunk headers are loaded to find the latest timestamp.
+    pub fn status(&mut self) -> RecordingStatus {
+        match &self.end {
+            Some(end) if end.header.clean => RecordingStatus::Finished {
+                end_time: end.header.end_time.clone(),
+            },
+            Some(end) => RecordingStatus::FinishedWithErrors {
+                end_time: end.header.end_time.clone(),
+                write_errors: end.header.stats.write_errors,
+            },
+            None => RecordingStatus::Unfinished {
+                latest_timestamp: self
+                    .chunk_headers_lossy()
+                    .flatten()
+                    .map(|header| {
+                        header
+                            .latest_timestamp
+                            .to_abs_timestamp(header.interval.base_time)
+                    })
+                    .max(),
+            },
+        }
+    }
+
     pub fn chunks_lossy(&mut self) -> impl DoubleEndedIterator<Item = Option<&Chunk>> {
         self.chunks.iter_mut().map(|loader| {
             loader.ensure_chunk();
@@ -53,8 +135,13 @@ impl Recording {
 }
 
 #[derive(Clone, Debug)]
-enum ChunkReadError {
+pub(crate) enum ChunkReadError {
     ReadError,
+    IncompatibleVersion(#[expect(unused)] FormatIdentifier),
+    InvalidHeader {
+        #[expect(unused)]
+        error: postcard::Error,
+    },
     DeserializeError {
         #[expect(unused)]
         index: usize,
@@ -108,10 +195,16 @@ impl ChunkLoader {
         if let ChunkLoaderState::Unloaded = self.state {
             let mut file = fs::File::open(&self.path.path).unwrap();
             let mut buffer = vec![0_u8; 24];
-            let (_identifier, _): (FormatIdentifier, _) =
+            let (identifier, _): (FormatIdentifier, _) =
                 postcard::from_io((&mut file, buffer.as_mut_slice())).unwrap();
+            // A chunk which can't be read is left unloaded.
+            let Some(format) = ChunkFormat::from_identifier(&identifier) else {
+                return;
+            };
 
-            let (header, _) = postcard::from_io((&mut file, Vec::new().as_mut_slice())).unwrap();
+            let (header, _) = format
+                .header_from_io((&mut file, Vec::new().as_mut_slice()))
+                .unwrap();
 
             self.state = ChunkLoaderState::Header(header);
         }
@@ -138,7 +231,7 @@ impl ChunkLoader {
     }
 }
 
-fn read_chunk_from_io<IO>(reader: IO) -> Result<Chunk, ChunkReadError>
+pub(crate) fn read_chunk_from_io<IO>(reader: IO) -> Result<Chunk, ChunkReadError>
 where
     IO: io::Read + io::Seek,
 {
@@ -153,14 +246,19 @@ where
     let mut buffer = vec![0_u8; 1024];
     let mut file_buffer = (&mut reader, buffer.as_mut_slice());
 
-    // TODO(hds): Should we validate the identifier?
-    let (_identifier, _): (FormatIdentifier, _) = postcard::from_io(file_buffer).unwrap();
+    let (identifier, _): (FormatIdentifier, _) =
+        postcard::from_io(file_buffer).map_err(|error| ChunkReadError::InvalidHeader { error })?;
+    // Chunks written with an earlier version of the format are converted as they are read.
+    let format = ChunkFormat::from_identifier(&identifier)
+        .ok_or(ChunkReadError::IncompatibleVersion(identifier))?;
 
-    let (header, _): (ChunkHeader, _) =
-        postcard::from_io((&mut reader, Vec::new().as_mut_slice())).unwrap();
+    let (header, _): (ChunkHeader, _) = format
+        .header_from_io((&mut reader, Vec::new().as_mut_slice()))
+        .map_err(|error| ChunkReadError::InvalidHeader { error })?;
 
     let (seq_chunk_len, _): (usize, _) =
-        postcard::from_io((&mut reader, Vec::new().as_mut_slice())).unwrap();
+        postcard::from_io((&mut reader, Vec::new().as_mut_slice()))
+            .map_err(|error| ChunkReadError::InvalidHeader { error })?;
     let mut seq_chunks: Vec<SeqChunk> = Vec::with_capacity(seq_chunk_len);
 
     file_buffer = (&mut reader, buffer.as_mut_slice());
@@ -191,7 +289,7 @@ where
                 continue;
             }
 
-            break match postcard::from_io(file_buffer) {
+            break match format.seq_chunk_from_io(header.interval.base_time, file_buffer) {
                 Ok(result) => result,
                 Err(postcard::Error::DeserializeUnexpectedEnd) => {
                     let new_size = buffer.len() * 2;
@@ -237,15 +335,33 @@ pub fn from_path(recording_path: String) -> Result<Recording, RecordingReadError
     let meta =
         ChunkedMeta::try_from_io(meta_file).map_err(RecordingReadError::ReadingMetaFailed)?;
 
-    let current = current_software_version();
-
-    if !current.can_read_version(&meta.header.format_identifiers[0]) {
+    if ChunkFormat::from_identifier(&meta.header.format_identifiers[0]).is_none() {
         return Err(RecordingReadError::IncompatibleVersion(
             meta.header.format_identifiers[0].clone(),
         ));
     }
 
-    let mut chunks = Vec::new();
+    // An end file which can't be read is treated like a missing one, the recording is unfinished.
+    // The chunks are still readable either way.
+    let end_path = recording_path.join("end.rfr");
+    let end = fs::File::open(&end_path)
+        .ok()
+        .and_then(|end_file| ChunkedEnd::try_from_io(end_file).ok());
+
+    let callsites_path = recording_path.join("callsites.rfr");
+    let callsites = match fs::File::open(&callsites_path) {
+        Ok(callsites_file) => ChunkedCallsites::try_from_io(callsites_file)
+            .map_err(RecordingReadError::ReadingCallsitesFailed)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => ChunkedCallsites::new(Vec::new()),
+        Err(err) => return Err(RecordingReadError::CallsitesFileNotReadable(err)),
+    };
+    let mut callsite_indices = HashMap::new();
+    for (idx, callsite) in callsites.callsites.iter().enumerate() {
+        // The writer doesn't write duplicate callsite Ids, but if there are any, keep the first.
+        callsite_indices.entry(callsite.callsite_id).or_insert(idx);
+    }
+
+    let mut chunk_paths = Vec::new();
     for entry in WalkDir::new(recording_path).sort_by_file_name() {
         let entry = entry.map_err(RecordingReadError::FilesystemError)?;
         if !entry.file_type().is_file() {
@@ -254,13 +370,13 @@ pub fn from_path(recording_path: String) -> Result<Recording, RecordingReadError
         }
 
         match entry.file_name().to_str() {
-            Some("meta.rfr") | Some("callsites.rfr") => {
+            Some("meta.rfr") | Some("callsites.rfr") | Some("end.rfr") => {
                 // We've already read the meta data, so we'll skip it (and any other metadata files).
                 continue;
             }
             Some(file_name) if file_name.ends_with(".rfr") => {
                 // We assume that this is a chunk
-                chunks.push(ChunkPath::new(entry.clone().into_path()).into());
+                chunk_paths.push(entry.clone().into_path());
             }
             _ => {}
         }
@@ -268,7 +384,36 @@ pub fn from_path(recording_path: String) -> Result<Recording, RecordingReadError
         println!("dir entry: {:?}", entry.file_name());
     }
 
-    Ok(Recording { meta, chunks })
+    // A partial chunk (a checkpoint of a chunk which was still being recorded) is superseded by
+    // the complete chunk if it was written.
+    let complete_paths: HashSet<PathBuf> = chunk_paths
+        .iter()
+        .filter(|path| complete_chunk_path(path).is_none())
+        .cloned()
+        .collect();
+    let chunks = chunk_paths
+        .into_iter()
+        .filter(|path| match complete_chunk_path(path) {
+            Some(complete_path) => !complete_paths.contains(&complete_path),
+            None => true,
+        })
+        .map(|path| ChunkPath::new(path).into())
+        .collect();
+
+    Ok(Recording {
+        meta,
+        end,
+        callsites,
+        callsite_indices,
+        chunks,
+    })
+}
+
+/// If `path` is a partial chunk, returns the path of the complete chunk which supersedes it.
+fn complete_chunk_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let stem = file_name.strip_suffix(PARTIAL_CHUNK_SUFFIX)?;
+    Some(path.with_file_name(format!("{stem}.rfr")))
 }
 
 #[derive(Debug)]
@@ -278,5 +423,7 @@ pub enum RecordingReadError {
     MetaFileNotReadable(io::Error),
     ReadingMetaFailed(MetaTryFromIoError),
     IncompatibleVersion(FormatIdentifier),
+    CallsitesFileNotReadable(io::Error),
+    ReadingCallsitesFailed(CallsitesTryFromIoError),
     FilesystemError(walkdir::Error),
 }
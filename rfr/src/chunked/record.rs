@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{Event, InstrumentationId, Waker, chunked::ChunkTimestamp};
+use crate::{Event, InstrumentationId, PollOp, StateUpdate, Waker, chunked::ChunkTimestamp};
 
 /// A record containing timing metadata and record data.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -33,4 +33,82 @@ pub enum RecordData {
     WakerWakeByRef { waker: Waker },
     WakerClone { waker: Waker },
     WakerDrop { waker: Waker },
+    ResourceNew { iid: InstrumentationId },
+    ResourceDrop { iid: InstrumentationId },
+    AsyncOpNew { iid: InstrumentationId },
+    AsyncOpPollStart { iid: InstrumentationId },
+    AsyncOpPollEnd { iid: InstrumentationId },
+    AsyncOpDrop { iid: InstrumentationId },
+    PollOp { poll_op: PollOp },
+    ResourceStateUpdate { update: StateUpdate },
+    AsyncOpStateUpdate { update: StateUpdate },
+}
+
+impl RecordData {
+    /// The number of different kinds of record data.
+    pub(crate) const KIND_COUNT: usize = 22;
+
+    /// The names of the kinds of record data, indexed by [`kind_index`].
+    ///
+    /// [`kind_index`]: fn@Self::kind_index
+    const KIND_NAMES: [&'static str; Self::KIND_COUNT] = [
+        "SpanNew",
+        "SpanEnter",
+        "SpanExit",
+        "SpanClose",
+        "Event",
+        "TaskNew",
+        "TaskPollStart",
+        "TaskPollEnd",
+        "TaskDrop",
+        "WakerWake",
+        "WakerWakeByRef",
+        "WakerClone",
+        "WakerDrop",
+        "ResourceNew",
+        "ResourceDrop",
+        "AsyncOpNew",
+        "AsyncOpPollStart",
+        "AsyncOpPollEnd",
+        "AsyncOpDrop",
+        "PollOp",
+        "ResourceStateUpdate",
+        "AsyncOpStateUpdate",
+    ];
+
+    /// The name of this kind of record data, which is the name of the variant.
+    pub fn kind_name(&self) -> &'static str {
+        Self::KIND_NAMES[self.kind_index()]
+    }
+
+    pub(crate) fn kind_name_from_index(index: usize) -> &'static str {
+        Self::KIND_NAMES[index]
+    }
+
+    pub(crate) fn kind_index(&self) -> usize {
+        match self {
+            Self::SpanNew { .. } => 0,
+            Self::SpanEnter { .. } => 1,
+            Self::SpanExit { .. } => 2,
+            Self::SpanClose { .. } => 3,
+            Self::Event { .. } => 4,
+            Self::TaskNew { .. } => 5,
+            Self::TaskPollStart { .. } => 6,
+            Self::TaskPollEnd { .. } => 7,
+            Self::TaskDrop { .. } => 8,
+            Self::WakerWake { .. } => 9,
+            Self::WakerWakeByRef { .. } => 10,
+            Self::WakerClone { .. } => 11,
+            Self::WakerDrop { .. } => 12,
+            Self::ResourceNew { .. } => 13,
+            Self::ResourceDrop { .. } => 14,
+            Self::AsyncOpNew { .. } => 15,
+            Self::AsyncOpPollStart { .. } => 16,
+            Self::AsyncOpPollEnd { .. } => 17,
+            Self::AsyncOpDrop { .. } => 18,
+            Self::PollOp { .. } => 19,
+            Self::ResourceStateUpdate { .. } => 20,
+            Self::AsyncOpStateUpdate { .. } => 21,
+        }
+    }
 }
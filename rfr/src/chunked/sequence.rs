@@ -3,25 +3,33 @@
 //! Each chunk in a chunked recording is made up of one or more sequence chunks ([`SeqChunk`]). Each sequence
 //! chunk contains an in-order series of records and the objects referenced by those records.
 //!
+//! If an object referenced by a record isn't available when the record is appended, the record is
+//! kept and the object's instrumentation id is stored in the sequence chunk's unresolved ids
+//! instead.
+//!
 //! Sequence chunks are generally used to model records from a single thread (as they can be
 //! recorded in order). Sequences can be tracked across multiple chunks by the sequence identifier
-//! [`SeqId`].
+//! [`SeqId`]. The thread that a sequence was recorded on is described by [`SeqThread`].
 
 use std::{
-    cell::Cell,
+    cell::OnceCell,
     collections::{HashMap, HashSet},
     io,
     sync::{
-        Mutex,
+        Arc, Mutex,
         atomic::{AtomicU64, Ordering},
     },
+    thread,
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    AbsTimestamp, InstrumentationId,
-    chunked::{AbsTimestampSecs, ChunkInterval, ChunkTimestamp, Object, Record, RecordData},
+    AbsTimestamp, InstrumentationId, Parent,
+    chunked::{
+        AbsTimestampSecs, ChunkInterval, ChunkTimestamp, Object, Record, RecordData, WriteError,
+        stats::WriterCounters,
+    },
 };
 
 /// Sequence chunk
@@ -38,6 +46,9 @@ use crate::{
 pub struct SeqChunk {
     pub header: SeqChunkHeader,
     pub objects: Vec<Object>,
+    /// The instrumentation ids referenced by records in this sequence chunk for which no object
+    /// was available.
+    pub unresolved_iids: Vec<InstrumentationId>,
     pub records: Vec<Record>,
 }
 
@@ -49,6 +60,8 @@ pub struct SeqChunkHeader {
     pub seq_id: SeqId,
     pub earliest_timestamp: ChunkTimestamp,
     pub latest_timestamp: ChunkTimestamp,
+    /// The thread which this sequence was recorded on.
+    pub thread: SeqThread,
 }
 
 /// Sequence identifier
@@ -64,22 +77,24 @@ impl From<u64> for SeqId {
 }
 
 impl SeqId {
-    const INVALID: SeqId = Self(0);
-
-    fn current() -> Self {
+    /// Returns the sequence for the current thread, allocating one the first time that it is
+    /// called on a thread.
+    fn current() -> (Self, SeqThread) {
         static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
         thread_local! {
-            pub static THREAD_ID: Cell<SeqId> = const { Cell::new(SeqId::INVALID) };
+            pub static CURRENT: OnceCell<(SeqId, SeqThread)> = const { OnceCell::new() };
         }
 
-        let current = THREAD_ID.get();
-        if current == Self::INVALID {
-            let new_current = Self(NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst));
-            THREAD_ID.set(new_current);
-            new_current
-        } else {
+        CURRENT.with(|current| {
             current
-        }
+                .get_or_init(|| {
+                    (
+                        Self(NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst)),
+                        SeqThread::current(),
+                    )
+                })
+                .clone()
+        })
     }
 
     pub fn as_u64(&self) -> u64 {
@@ -87,35 +102,93 @@ impl SeqId {
     }
 }
 
+/// The thread that a sequence was recorded on.
+///
+/// Each thread which records anything gets its own sequence, so this is the same for all the
+/// sequence chunks with the same [`SeqId`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SeqThread {
+    /// The name of the thread, if it has one (e.g. `tokio-runtime-worker`).
+    pub name: Option<String>,
+    /// The operating system's identifier for the thread, if it could be determined.
+    pub os_thread_id: Option<u64>,
+    /// The time at which the sequence was first used on this thread.
+    pub first_seen: AbsTimestamp,
+}
+
+impl SeqThread {
+    fn current() -> Self {
+        Self {
+            name: thread::current().name().map(ToOwned::to_owned),
+            os_thread_id: os_thread_id(),
+            first_seen: AbsTimestamp::now(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn os_thread_id() -> Option<u64> {
+    // `/proc/thread-self` links to `<pid>/task/<tid>` for the calling thread.
+    std::fs::read_link("/proc/thread-self")
+        .ok()?
+        .file_name()?
+        .to_str()?
+        .parse()
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn os_thread_id() -> Option<u64> {
+    None
+}
+
 #[derive(Debug)]
 pub struct SeqChunkBuffer {
     interval: ChunkInterval,
     buffer: Mutex<Buffer>,
+    counters: Option<Arc<WriterCounters>>,
 }
 
 #[derive(Debug)]
 struct Buffer {
     header: SeqChunkHeader,
     objects: HashMap<InstrumentationId, Vec<u8>>,
-    missing_objects: HashSet<InstrumentationId>,
+    unresolved_iids: HashSet<InstrumentationId>,
     record_count: usize,
     records: Vec<u8>,
 }
 
 impl SeqChunkBuffer {
     pub fn new(interval: ChunkInterval) -> Self {
+        let (seq_id, thread) = SeqId::current();
         let buffer = Mutex::new(Buffer {
             header: SeqChunkHeader {
-                seq_id: SeqId::current(),
+                seq_id,
                 earliest_timestamp: interval.end_time,
                 latest_timestamp: interval.start_time,
+                thread,
             },
             objects: HashMap::new(),
-            missing_objects: HashSet::new(),
+            unresolved_iids: HashSet::new(),
             record_count: 0,
             records: Vec::new(),
         });
-        Self { interval, buffer }
+        Self {
+            interval,
+            buffer,
+            counters: None,
+        }
+    }
+
+    /// Create a sequence chunk buffer which updates the writer's counters.
+    pub(crate) fn new_with_counters(
+        interval: ChunkInterval,
+        counters: Arc<WriterCounters>,
+    ) -> Self {
+        Self {
+            counters: Some(counters),
+            ..Self::new(interval)
+        }
     }
 
     pub fn interval(&self) -> &ChunkInterval {
@@ -131,6 +204,11 @@ impl SeqChunkBuffer {
         buffer.header.seq_id
     }
 
+    pub fn thread(&self) -> SeqThread {
+        let buffer = self.buffer.lock().expect("poisoned");
+        buffer.header.thread.clone()
+    }
+
     pub fn earliest_timestamp(&self) -> ChunkTimestamp {
         let buffer = self.buffer.lock().expect("poisoned");
         buffer.header.earliest_timestamp
@@ -155,87 +233,158 @@ impl SeqChunkBuffer {
     // FIXME(hds): modify to take an absolute timestamp and a record instead of a Record. Then this
     // function will convert the timestamp to a chunked timestamp and validate it at the same time.
     // If it is invalid, an error will be returned.
-    pub fn append_record<FnGetObjects>(&self, record: Record, get_objects: FnGetObjects)
+    /// Append a record to this sequence chunk.
+    ///
+    /// Any objects referenced by the record which aren't already in this sequence chunk are
+    /// requested from `get_objects`. If an object isn't available, the record is still appended and
+    /// the object's instrumentation id is added to the unresolved ids of this sequence chunk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record or one of the objects can't be serialized. The record is
+    /// not appended in that case.
+    pub fn append_record<FnGetObjects>(
+        &self,
+        record: Record,
+        get_objects: FnGetObjects,
+    ) -> Result<(), WriteError>
     where
         FnGetObjects: FnOnce(&[InstrumentationId]) -> Vec<Option<Object>>,
     {
         let mut buffer = self.buffer.lock().expect("poisoned");
-        let mut missing_task_ids = Vec::new();
+        let mut missing_iids = Vec::new();
         match &record.data {
-            RecordData::TaskNew { iid }
+            RecordData::SpanNew { iid }
+            | RecordData::SpanEnter { iid }
+            | RecordData::SpanExit { iid }
+            | RecordData::SpanClose { iid }
+            | RecordData::TaskNew { iid }
             | RecordData::TaskPollStart { iid }
             | RecordData::TaskPollEnd { iid }
-            | RecordData::TaskDrop { iid } => {
-                if !buffer.objects.contains_key(iid) {
-                    missing_task_ids.push(*iid);
+            | RecordData::TaskDrop { iid }
+            | RecordData::ResourceNew { iid }
+            | RecordData::ResourceDrop { iid }
+            | RecordData::AsyncOpNew { iid }
+            | RecordData::AsyncOpPollStart { iid }
+            | RecordData::AsyncOpPollEnd { iid }
+            | RecordData::AsyncOpDrop { iid } => {
+                if !buffer.is_known(iid) {
+                    missing_iids.push(*iid);
                 }
             }
             RecordData::WakerWake { waker }
             | RecordData::WakerWakeByRef { waker }
             | RecordData::WakerClone { waker }
             | RecordData::WakerDrop { waker } => {
-                if !buffer.objects.contains_key(&waker.task_iid) {
-                    missing_task_ids.push(waker.task_iid);
+                if !buffer.is_known(&waker.task_iid) {
+                    missing_iids.push(waker.task_iid);
                 }
                 if let Some(context_task_id) = &waker.context
                     && context_task_id != &waker.task_iid
-                    && !buffer.objects.contains_key(context_task_id)
+                    && !buffer.is_known(context_task_id)
                 {
-                    missing_task_ids.push(*context_task_id);
+                    missing_iids.push(*context_task_id);
                 }
             }
-            RecordData::SpanNew { iid }
-            | RecordData::SpanEnter { iid }
-            | RecordData::SpanExit { iid }
-            | RecordData::SpanClose { iid } => {
-                // TODO(hds): Do something with spans
-                _ = iid;
+            RecordData::PollOp { poll_op } => {
+                let iids = [
+                    Some(poll_op.resource_iid),
+                    poll_op.async_op_iid,
+                    poll_op.context,
+                ];
+                for iid in iids.into_iter().flatten() {
+                    if !buffer.is_known(&iid) && !missing_iids.contains(&iid) {
+                        missing_iids.push(iid);
+                    }
+                }
+            }
+            RecordData::ResourceStateUpdate { update }
+            | RecordData::AsyncOpStateUpdate { update } => {
+                if !buffer.is_known(&update.iid) {
+                    missing_iids.push(update.iid);
+                }
             }
             RecordData::Event { event } => {
-                // TODO(hds): Do something with events
-                _ = event;
+                if let Parent::Explicit { iid } = &event.parent
+                    && !buffer.is_known(iid)
+                {
+                    missing_iids.push(*iid);
+                }
             }
         }
 
         // FIXME(hds): What if the 2 vecs are different sizes?
-        let missing_tasks = get_objects(missing_task_ids.as_slice());
-        for (task_id, task) in missing_task_ids.into_iter().zip(missing_tasks.into_iter()) {
-            match task {
-                Some(task) => {
-                    let task_buffer = postcard::to_stdvec(&task).unwrap();
-                    buffer.objects.insert(task_id, task_buffer);
+        let objects = get_objects(missing_iids.as_slice());
+        for (iid, object) in missing_iids.into_iter().zip(objects) {
+            match object {
+                Some(object) => {
+                    let object_buffer =
+                        postcard::to_stdvec(&object).map_err(WriteError::Serialization)?;
+                    buffer.objects.insert(iid, object_buffer);
                 }
                 None => {
-                    // TODO(hds): Currently we don't do anything with this information, should we?
-                    //            Also, should we actually return early here or should we continue?
-                    //            If we do want to return early, we should probably not write any
-                    //            task data to `buffer.objects`.
-                    buffer.missing_objects.insert(task_id);
-                    return;
+                    // The record is kept, readers will find the iid in the unresolved ids.
+                    buffer.unresolved_iids.insert(iid);
+                    if let Some(counters) = &self.counters {
+                        counters.unresolved_object();
+                    }
                 }
             }
         }
 
+        // Serialize separately, so that a failure doesn't leave a partial record in the buffer.
+        let record_buffer = postcard::to_stdvec(&record).map_err(WriteError::Serialization)?;
         if buffer.record_count == 0 {
             buffer.header.earliest_timestamp = record.meta.timestamp;
         }
         buffer.header.latest_timestamp = record.meta.timestamp;
-        postcard::to_io(&record, &mut buffer.records).unwrap();
+        buffer.records.extend_from_slice(&record_buffer);
         buffer.record_count += 1;
+        if let Some(counters) = &self.counters {
+            counters.record_written(&record.data);
+        }
+
+        Ok(())
+    }
+
+    /// The instrumentation ids referenced by records for which no object was available.
+    pub fn unresolved_iids(&self) -> Vec<InstrumentationId> {
+        let buffer = self.buffer.lock().expect("poisoned");
+        let mut unresolved_iids: Vec<_> = buffer.unresolved_iids.iter().copied().collect();
+        unresolved_iids.sort();
+        unresolved_iids
     }
 
-    pub fn write(&self, writer: impl io::Write) {
+    /// Write the serialized sequence chunk to `writer`.
+    pub fn write(&self, writer: impl io::Write) -> Result<(), WriteError> {
         let mut writer = writer;
         let buffer = self.buffer.lock().expect("poisoned");
 
-        postcard::to_io(&buffer.header, &mut writer).unwrap();
+        postcard::to_io(&buffer.header, &mut writer).map_err(WriteError::Serialization)?;
 
-        postcard::to_io(&buffer.objects.len(), &mut writer).unwrap();
+        postcard::to_io(&buffer.objects.len(), &mut writer).map_err(WriteError::Serialization)?;
         for object_data in buffer.objects.values() {
-            writer.write_all(object_data.as_slice()).unwrap();
+            writer
+                .write_all(object_data.as_slice())
+                .map_err(WriteError::Io)?;
         }
 
-        postcard::to_io(&buffer.record_count, &mut writer).unwrap();
-        writer.write_all(buffer.records.as_slice()).unwrap();
+        let mut unresolved_iids: Vec<_> = buffer.unresolved_iids.iter().collect();
+        unresolved_iids.sort();
+        postcard::to_io(&unresolved_iids, &mut writer).map_err(WriteError::Serialization)?;
+
+        postcard::to_io(&buffer.record_count, &mut writer).map_err(WriteError::Serialization)?;
+        writer
+            .write_all(buffer.records.as_slice())
+            .map_err(WriteError::Io)?;
+
+        Ok(())
+    }
+}
+
+impl Buffer {
+    /// Whether the object for `iid` has already been requested for this sequence chunk.
+    fn is_known(&self, iid: &InstrumentationId) -> bool {
+        self.objects.contains_key(iid) || self.unresolved_iids.contains(iid)
     }
 }
@@ -0,0 +1,137 @@
+//! Chunked writer statistics
+//!
+//! The writer keeps live counters of what it has recorded and written. A snapshot of these
+//! counters can be taken at any time with [`ChunkedWriter::stats`], and a final snapshot is stored
+//! in the end file when the recording is finished.
+//!
+//! [`ChunkedWriter::stats`]: fn@crate::chunked::ChunkedWriter::stats
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunked::RecordData;
+
+/// A snapshot of the statistics of a chunked writer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WriterStats {
+    /// The number of records appended to the recording for each kind of [`RecordData`], keyed by
+    /// [`RecordData::kind_name`].
+    pub records_written: BTreeMap<String, u64>,
+    /// The number of objects referenced by records which weren't available. The records are kept
+    /// and the objects are marked as unresolved in their sequence chunk.
+    pub unresolved_objects: u64,
+    /// The number of records for a chunk which had already been written.
+    pub late_records: u64,
+    /// The number of chunks written.
+    pub chunks_written: u64,
+    /// The total size in bytes of all the chunks written.
+    pub chunk_bytes_total: u64,
+    /// The size in bytes of the most recently written chunk.
+    pub chunk_bytes_last: u64,
+    /// The size in bytes of the largest chunk written.
+    pub chunk_bytes_max: u64,
+    /// The number of chunks which couldn't be written because of an error.
+    pub write_errors: u64,
+    /// The number of partial chunks which couldn't be written because of an error. The complete
+    /// chunk may still have been written, so these don't make a recording unclean.
+    pub checkpoint_errors: u64,
+    /// How far behind schedule the writer loop was the last time it ran, in microseconds.
+    pub writer_loop_latency_last_micros: u64,
+    /// The furthest behind schedule the writer loop has been, in microseconds.
+    pub writer_loop_latency_max_micros: u64,
+}
+
+impl WriterStats {
+    /// The total number of records appended to the recording.
+    pub fn total_records_written(&self) -> u64 {
+        self.records_written.values().sum()
+    }
+}
+
+/// The live counters behind [`WriterStats`].
+#[derive(Debug, Default)]
+pub(crate) struct WriterCounters {
+    records_written: [AtomicU64; RecordData::KIND_COUNT],
+    unresolved_objects: AtomicU64,
+    late_records: AtomicU64,
+    chunks_written: AtomicU64,
+    chunk_bytes_total: AtomicU64,
+    chunk_bytes_last: AtomicU64,
+    chunk_bytes_max: AtomicU64,
+    write_errors: AtomicU64,
+    checkpoint_errors: AtomicU64,
+    writer_loop_latency_last_micros: AtomicU64,
+    writer_loop_latency_max_micros: AtomicU64,
+}
+
+impl WriterCounters {
+    pub(crate) fn record_written(&self, data: &RecordData) {
+        self.records_written[data.kind_index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn unresolved_object(&self) {
+        self.unresolved_objects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn late_record(&self) {
+        self.late_records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn chunk_written(&self, bytes: u64) {
+        self.chunks_written.fetch_add(1, Ordering::Relaxed);
+        self.chunk_bytes_total.fetch_add(bytes, Ordering::Relaxed);
+        self.chunk_bytes_last.store(bytes, Ordering::Relaxed);
+        self.chunk_bytes_max.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn checkpoint_error(&self) {
+        self.checkpoint_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn writer_loop_latency(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.writer_loop_latency_last_micros
+            .store(micros, Ordering::Relaxed);
+        self.writer_loop_latency_max_micros
+            .fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> WriterStats {
+        let records_written = self
+            .records_written
+            .iter()
+            .enumerate()
+            .filter_map(|(index, count)| {
+                let count = count.load(Ordering::Relaxed);
+                (count > 0).then(|| (RecordData::kind_name_from_index(index).to_owned(), count))
+            })
+            .collect();
+
+        WriterStats {
+            records_written,
+            unresolved_objects: self.unresolved_objects.load(Ordering::Relaxed),
+            late_records: self.late_records.load(Ordering::Relaxed),
+            chunks_written: self.chunks_written.load(Ordering::Relaxed),
+            chunk_bytes_total: self.chunk_bytes_total.load(Ordering::Relaxed),
+            chunk_bytes_last: self.chunk_bytes_last.load(Ordering::Relaxed),
+            chunk_bytes_max: self.chunk_bytes_max.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            checkpoint_errors: self.checkpoint_errors.load(Ordering::Relaxed),
+            writer_loop_latency_last_micros: self
+                .writer_loop_latency_last_micros
+                .load(Ordering::Relaxed),
+            writer_loop_latency_max_micros: self
+                .writer_loop_latency_max_micros
+                .load(Ordering::Relaxed),
+        }
+    }
+}
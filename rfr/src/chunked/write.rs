@@ -1,10 +1,12 @@
 use std::{
     cell::RefCell,
-    error, fmt, fs, io,
+    collections::{BTreeMap, VecDeque},
+    error, fmt, fs,
+    io::{self, BufWriter},
     path::{Path, PathBuf},
     sync::{
-        Arc, Condvar, Mutex,
-        atomic::{self, AtomicBool},
+        Arc, Condvar, Mutex, MutexGuard,
+        atomic::{self, AtomicBool, AtomicU64, AtomicUsize},
     },
     time::{Duration, Instant},
 };
@@ -12,77 +14,530 @@ use std::{
 use jiff::{Timestamp, Zoned, tz::TimeZone};
 
 use crate::chunked::{
-    AbsTimestampSecs, ChunkedCallsitesWriter, ChunkedMeta, current_software_version,
+    AbsTimestampSecs, Chunk, ChunkedCallsitesWriter, ChunkedEnd, ChunkedMeta, ClockAdjustment,
+    PARTIAL_CHUNK_SUFFIX, PushCallsiteResult, WriterStats, current_software_version,
+    read::read_chunk_from_io, stats::WriterCounters,
 };
 use crate::{
-    AbsTimestamp, Callsite,
+    AbsTimestamp, Callsite, CallsiteId,
     chunked::{ChunkHeader, ChunkInterval, SeqChunkBuffer},
 };
 
-#[derive(Debug)]
-pub struct ChunkedWriter {
-    root_dir: PathBuf,
-    base_time: AbsTimestampSecs,
+/// How far the wall clock must move relative to the recording clock to be stored as an adjustment.
+const CLOCK_ADJUSTMENT_THRESHOLD_MICROS: u64 = 1_000;
+
+/// Limits on how much of a chunked recording is kept on disk.
+///
+/// When a limit is exceeded, the oldest chunk files are deleted until the recording is within the
+/// limits again. Partial chunks and chunks of late records are included, and are deleted together
+/// with the other files for the same interval. The recording wide files (`meta.rfr` and
+/// `callsites.rfr`) are never deleted.
+///
+/// The default retention keeps all chunks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The maximum length of execution time to keep chunks for, measured back from the end of the
+    /// most recently written chunk.
+    pub max_duration: Option<Duration>,
+    /// The maximum total size in bytes of all the chunk files.
+    pub max_bytes: Option<u64>,
+}
 
-    /// The length of time a chunk is "responsible" for. This value must either be a multiple of
-    /// seconds (multiple of 1_000_000) or a divisor of a whole second (divisor of 1_000_000).
-    chunk_period_micros: u32,
+impl Retention {
+    /// Keep all chunks, this is the default.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
 
-    closed: AtomicBool,
+    /// Keep chunks covering at most `max_duration` of execution time.
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
 
-    callsites_writer: Mutex<ChunkedCallsitesWriter<fs::File>>,
-    chunk_buffers: Mutex<Vec<ChunkBuffer>>,
-    notifiers: Mutex<Vec<ChunkWriteNotifier>>,
+    /// Keep at most `max_bytes` worth of chunk files.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max_duration.is_none() && self.max_bytes.is_none()
+    }
 }
 
-impl ChunkedWriter {
-    pub fn try_new<P>(root_dir: P) -> Result<Self, NewChunkedWriterError>
+/// How the chunk files are arranged within a chunked recording's directory.
+///
+/// When the chunk period is shorter than a second, the start of the chunk within its second is
+/// appended to the file name in microseconds, e.g. `chunk-<MM-SS>.<uuuuuu>.rfr`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DirectoryLayout {
+    /// Chunks are grouped into a directory per month and a directory per day and hour within
+    /// that: `<YYYY-MM>/<DD-HH>/chunk-<MM-SS>.rfr`. This is the default.
+    #[default]
+    Nested,
+    /// All chunks are placed directly in the recording directory:
+    /// `chunk-<YYYY-MM-DD>T<HH-MM-SS>.rfr`.
+    Flat,
+}
+
+impl DirectoryLayout {
+    fn ensure_dir(&self, root_dir: &Path, time: &AbsTimestampSecs) -> io::Result<()> {
+        fs::create_dir_all(self.dir_path(root_dir, time))
+    }
+
+    fn dir_path(&self, root_dir: &Path, time: &AbsTimestampSecs) -> PathBuf {
+        match self {
+            Self::Nested => dir_path_from_utc(root_dir, &to_utc(time)),
+            Self::Flat => root_dir.to_owned(),
+        }
+    }
+
+    fn chunk_path(&self, root_dir: &Path, interval: &ChunkInterval) -> PathBuf {
+        self.chunk_path_with_suffix(root_dir, interval, ".rfr")
+    }
+
+    fn partial_chunk_path(&self, root_dir: &Path, interval: &ChunkInterval) -> PathBuf {
+        self.chunk_path_with_suffix(root_dir, interval, PARTIAL_CHUNK_SUFFIX)
+    }
+
+    /// Returns a path which doesn't exist yet for an additional chunk file for `interval`, e.g.
+    /// `chunk-<MM-SS>.late-1.rfr`.
+    fn separate_chunk_path(&self, root_dir: &Path, interval: &ChunkInterval) -> PathBuf {
+        let mut n = 1_u32;
+        loop {
+            let path = self.chunk_path_with_suffix(root_dir, interval, &format!(".late-{n}.rfr"));
+            if !path.exists() {
+                return path;
+            }
+            n += 1;
+        }
+    }
+
+    fn chunk_path_with_suffix(
+        &self,
+        root_dir: &Path,
+        interval: &ChunkInterval,
+        suffix: &str,
+    ) -> PathBuf {
+        let ts_utc = to_utc(&interval.base_time);
+        let time = match self {
+            Self::Nested => ts_utc.strftime("%M-%S").to_string(),
+            Self::Flat => ts_utc.strftime("%Y-%m-%dT%H-%M-%S").to_string(),
+        };
+
+        let period_micros = interval.end_time.as_micros() - interval.start_time.as_micros();
+        let file_name = if period_micros < 1_000_000 {
+            // Sub-second chunks share their base time, the start offset tells them apart.
+            format!(
+                "chunk-{time}.{start:06}{suffix}",
+                start = interval.start_time.as_micros()
+            )
+        } else {
+            format!("chunk-{time}{suffix}")
+        };
+
+        self.dir_path(root_dir, &interval.base_time).join(file_name)
+    }
+}
+
+/// A builder for a [`ChunkedWriter`].
+///
+/// The defaults match [`ChunkedWriter::try_new`].
+#[derive(Debug, Clone)]
+pub struct ChunkedWriterBuilder {
+    chunk_period: Duration,
+    write_delay: Duration,
+    write_slack: Duration,
+    layout: DirectoryLayout,
+    retention: Retention,
+    checkpoint_interval: Option<Duration>,
+    tokio_version: Option<String>,
+    worker_threads: Option<u64>,
+    labels: BTreeMap<String, String>,
+}
+
+impl Default for ChunkedWriterBuilder {
+    fn default() -> Self {
+        Self {
+            chunk_period: Duration::from_secs(1),
+            write_delay: Duration::from_millis(150),
+            write_slack: Duration::from_millis(50),
+            layout: DirectoryLayout::default(),
+            retention: Retention::default(),
+            checkpoint_interval: None,
+            tokio_version: None,
+            worker_threads: None,
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+impl ChunkedWriterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the length of execution time that each chunk covers.
+    ///
+    /// The period must either be a whole number of seconds or evenly divide a second, with
+    /// microsecond precision. Otherwise building the writer will fail. The default is 1 second.
+    pub fn chunk_period(mut self, chunk_period: Duration) -> Self {
+        self.chunk_period = chunk_period;
+        self
+    }
+
+    /// Set how long after the end of a chunk's interval to wait before writing it.
+    ///
+    /// This gives other threads time to finish writing records to the chunk. The default is
+    /// 150 milliseconds.
+    pub fn write_delay(mut self, write_delay: Duration) -> Self {
+        self.write_delay = write_delay;
+        self
+    }
+
+    /// Set the extra time to wait after the next chunk is ready to be written.
+    ///
+    /// This is added to the duration returned from [`ChunkedWriter::write_completed_chunks`], so
+    /// that a writer loop doesn't wake up just before the next chunk is ready. The default is
+    /// 50 milliseconds.
+    pub fn write_slack(mut self, write_slack: Duration) -> Self {
+        self.write_slack = write_slack;
+        self
+    }
+
+    /// Set how chunk files are arranged within the recording directory.
+    pub fn directory_layout(mut self, layout: DirectoryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Set the limits on how much of the recording is kept on disk.
+    ///
+    /// This has no effect on a writer built with [`build_in_memory`].
+    ///
+    /// [`build_in_memory`]: fn@Self::build_in_memory
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Periodically write the chunks which are still being recorded to as partial chunk files.
+    ///
+    /// If the process is killed before a chunk is complete, the partial chunk file keeps what was
+    /// recorded up until the last checkpoint. Once the complete chunk has been written, its partial
+    /// chunk file is removed. By default, no checkpoints are written.
+    ///
+    /// This has no effect on a writer built with [`build_in_memory`].
+    ///
+    /// [`build_in_memory`]: fn@Self::build_in_memory
+    pub fn checkpoint_interval(mut self, checkpoint_interval: Duration) -> Self {
+        self.checkpoint_interval = Some(checkpoint_interval);
+        self
+    }
+
+    /// Set the version of Tokio being recorded, which is stored in the recording's metadata.
+    pub fn tokio_version(mut self, tokio_version: impl Into<String>) -> Self {
+        self.tokio_version = Some(tokio_version.into());
+        self
+    }
+
+    /// Set the number of runtime worker threads, which is stored in the recording's metadata.
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads as u64);
+        self
+    }
+
+    /// Add a key/value label to the recording's metadata.
+    ///
+    /// Labels can be used to tell recordings apart, e.g. by service or deployment. Setting a label
+    /// with the same key again replaces its value.
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Build a chunked writer which writes a new chunked recording at `root_dir`.
+    pub fn build<P>(self, root_dir: P) -> Result<ChunkedWriter, NewChunkedWriterError>
     where
         P: AsRef<Path>,
     {
+        let chunk_period_micros = self.validated_chunk_period_micros()?;
         let root_dir = root_dir.as_ref();
 
         let timestamp = AbsTimestamp::now();
         let base_time = AbsTimestampSecs::from(timestamp.clone());
-        let meta = ChunkedMeta::new(vec![current_software_version()]);
+        let meta = self.meta();
 
         if let Ok(true) = root_dir.try_exists() {
             return Err(NewChunkedWriterError::AlreadyExists);
         }
 
         fs::create_dir_all(root_dir).map_err(NewChunkedWriterError::CreateRecordingDirFailed)?;
-        Self::write_meta(root_dir, &meta)?;
+        ChunkedWriter::write_meta(root_dir, &meta)?;
 
         let callsites_path = Path::new(&root_dir).join("callsites.rfr");
         let callsites_file = fs::File::create(callsites_path)
             .map_err(|err| NewChunkedWriterError::WriteCallsitesFailed(WriteError::Io(err)))?;
-        let callsites_writer = ChunkedCallsitesWriter::try_new(callsites_file)
-            .map_err(NewChunkedWriterError::WriteCallsitesFailed)?;
+        let callsites_writer =
+            ChunkedCallsitesWriter::try_new(CallsitesOutput::File(callsites_file))
+                .map_err(NewChunkedWriterError::WriteCallsitesFailed)?;
 
-        // By default, chunks contain 1 second of execution time.
-        let chunk_period_micros = 1_000_000;
-        let writer = Self {
+        self.layout
+            .ensure_dir(root_dir, &base_time)
+            .map_err(NewChunkedWriterError::CreateRecordingDirFailed)?;
+
+        let storage = ChunkStorage::Disk {
             root_dir: root_dir.to_owned(),
-            base_time,
+            retention: self.retention.clone(),
+            written_chunks: Mutex::new(VecDeque::new()),
+        };
+        Ok(self.into_writer(storage, meta, callsites_writer, chunk_period_micros))
+    }
+
+    /// Build a chunked writer which keeps completed chunks in memory.
+    ///
+    /// Only the chunks needed to cover the last `ring_duration` of execution time are kept.
+    /// Nothing is written to disk until [`ChunkedWriter::dump_to`] is called.
+    pub fn build_in_memory(
+        self,
+        ring_duration: Duration,
+    ) -> Result<ChunkedWriter, NewChunkedWriterError> {
+        let chunk_period_micros = self.validated_chunk_period_micros()?;
+        let meta = self.meta();
+        let callsites_writer = ChunkedCallsitesWriter::try_new(CallsitesOutput::Discard)
+            .expect("discarding callsites output cannot fail");
+
+        let storage = ChunkStorage::Memory(Mutex::new(ChunkRing::new(ring_duration)));
+        Ok(self.into_writer(storage, meta, callsites_writer, chunk_period_micros))
+    }
+
+    fn meta(&self) -> ChunkedMeta {
+        let mut meta = ChunkedMeta::new(vec![current_software_version()]);
+        meta.header.runtime.tokio_version = self.tokio_version.clone();
+        meta.header.runtime.worker_threads = self.worker_threads;
+        meta.header.labels = self.labels.clone();
+        meta
+    }
+
+    /// Check the chunk period against the rules in [`ChunkInterval::from_timestamp_and_period`].
+    fn validated_chunk_period_micros(&self) -> Result<u32, NewChunkedWriterError> {
+        let invalid = || NewChunkedWriterError::InvalidChunkPeriod(self.chunk_period);
+        if !self.chunk_period.subsec_nanos().is_multiple_of(1_000) {
+            return Err(invalid());
+        }
+        let micros = u32::try_from(self.chunk_period.as_micros()).map_err(|_| invalid())?;
+
+        let is_valid = if micros > 1_000_000 {
+            micros.is_multiple_of(1_000_000)
+        } else {
+            micros > 0 && 1_000_000_u32.is_multiple_of(micros)
+        };
+        if is_valid { Ok(micros) } else { Err(invalid()) }
+    }
+
+    fn into_writer(
+        self,
+        storage: ChunkStorage,
+        meta: ChunkedMeta,
+        callsites_writer: ChunkedCallsitesWriter<CallsitesOutput>,
+        chunk_period_micros: u32,
+    ) -> ChunkedWriter {
+        ChunkedWriter {
+            storage,
+            meta,
+            layout: self.layout,
             chunk_period_micros,
-            closed: false.into(),
+            write_delay: self.write_delay,
+            write_slack: self.write_slack,
+            checkpoint_interval: self.checkpoint_interval,
+            last_checkpoint: Mutex::new(None),
+            clock: Mutex::new(ClockObserver::default()),
+            closed: Mutex::new(false),
+            closed_condvar: Condvar::new(),
+            finished: false.into(),
+            counters: Arc::new(WriterCounters::default()),
+            latest_written_end_micros: AtomicU64::new(0),
+            next_write_at_micros: AtomicU64::new(0),
             callsites_writer: Mutex::new(callsites_writer),
             chunk_buffers: Mutex::new(Vec::new()),
             notifiers: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// A chunk file which has been written to disk, tracked to enforce [`Retention`].
+#[derive(Debug)]
+struct WrittenChunk {
+    path: PathBuf,
+    start_time: AbsTimestamp,
+    end_time: AbsTimestamp,
+    size: u64,
+}
+
+/// Where completed chunks are stored.
+#[derive(Debug)]
+enum ChunkStorage {
+    /// Completed chunks are written to files in the recording directory.
+    Disk {
+        root_dir: PathBuf,
+        retention: Retention,
+        /// Chunks written to disk, ordered from oldest to newest.
+        written_chunks: Mutex<VecDeque<WrittenChunk>>,
+    },
+    /// Completed chunks are kept in memory until they are dumped to disk.
+    Memory(Mutex<ChunkRing>),
+}
+
+/// A ring of serialized chunks covering at least a fixed duration of execution time.
+#[derive(Debug)]
+struct ChunkRing {
+    ring_duration: Duration,
+    /// Serialized chunks, ordered from oldest to newest.
+    chunks: VecDeque<(ChunkInterval, Vec<u8>)>,
+}
+
+impl ChunkRing {
+    fn new(ring_duration: Duration) -> Self {
+        Self {
+            ring_duration,
+            chunks: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, interval: &ChunkInterval) -> Option<&[u8]> {
+        self.chunks
+            .iter()
+            .find(|(i, _)| i == interval)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    fn push(&mut self, interval: ChunkInterval, data: Vec<u8>) {
+        if let Some(existing) = self.chunks.iter_mut().find(|(i, _)| i == &interval) {
+            // The chunk has been serialized again (e.g. by `write_all_chunks`).
+            existing.1 = data;
+            return;
+        }
+
+        self.chunks.push_back((interval, data));
+        self.chunks
+            .make_contiguous()
+            .sort_by_key(|(interval, _)| interval.abs_start_time());
+
+        // Only discard the oldest chunk if the remaining chunks still cover the ring duration.
+        let Some(newest_end_time) = self.chunks.back().map(|(i, _)| i.abs_end_time()) else {
+            return;
         };
+        while let Some((next_oldest, _)) = self.chunks.get(1) {
+            let covered = newest_end_time
+                .as_duration_since_epoch()
+                .saturating_sub(next_oldest.abs_start_time().as_duration_since_epoch());
+            if covered < self.ring_duration {
+                break;
+            }
+            self.chunks.pop_front();
+        }
+    }
+}
+
+/// The output for the callsites file.
+///
+/// When chunks are kept in memory, there is no callsites file, the callsites are all available
+/// from [`ChunkedCallsitesWriter::chunked_callsites`] when they need to be written.
+#[derive(Debug)]
+enum CallsitesOutput {
+    File(fs::File),
+    Discard,
+}
+
+impl io::Write for CallsitesOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(buf),
+            Self::Discard => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Discard => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkedWriter {
+    storage: ChunkStorage,
+    meta: ChunkedMeta,
+    layout: DirectoryLayout,
+
+    /// The length of time a chunk is "responsible" for. This value must either be a multiple of
+    /// seconds (multiple of 1_000_000) or a divisor of a whole second (divisor of 1_000_000).
+    chunk_period_micros: u32,
+    /// How long after the end of a chunk's interval to wait before writing it.
+    write_delay: Duration,
+    /// Extra time added to the suggested wait until the next write.
+    write_slack: Duration,
+    /// How often to write partial chunk files for the chunks still being recorded to.
+    checkpoint_interval: Option<Duration>,
+    last_checkpoint: Mutex<Option<Instant>>,
+    clock: Mutex<ClockObserver>,
+
+    closed: Mutex<bool>,
+    /// Notified when the writer is closed, to wake up [`ChunkedWriter::sleep_unless_closed`].
+    closed_condvar: Condvar,
+    finished: AtomicBool,
+    counters: Arc<WriterCounters>,
+    /// The end of the latest chunk written, in microseconds since the UNIX epoch.
+    latest_written_end_micros: AtomicU64,
+    /// When the writer loop is next expected to call `write_completed_chunks`, in microseconds
+    /// since the UNIX epoch. Zero if it hasn't been called yet.
+    next_write_at_micros: AtomicU64,
+
+    callsites_writer: Mutex<ChunkedCallsitesWriter<CallsitesOutput>>,
+    chunk_buffers: Mutex<Vec<Arc<ChunkBuffer>>>,
+    notifiers: Mutex<Vec<ChunkWriteNotifier>>,
+}
 
-        let base_time = writer.base_time;
-        writer.ensure_dir(&base_time);
+impl ChunkedWriter {
+    /// Creates a new chunked writer at `root_dir` with the default configuration.
+    ///
+    /// Use [`builder`] to configure the writer.
+    ///
+    /// [`builder`]: fn@Self::builder
+    pub fn try_new<P>(root_dir: P) -> Result<Self, NewChunkedWriterError>
+    where
+        P: AsRef<Path>,
+    {
+        ChunkedWriterBuilder::new().build(root_dir)
+    }
 
-        Ok(writer)
+    /// Returns a builder to configure a new chunked writer.
+    pub fn builder() -> ChunkedWriterBuilder {
+        ChunkedWriterBuilder::new()
     }
 
     pub fn chunk_period_micros(&self) -> u32 {
         self.chunk_period_micros
     }
 
+    /// Returns whether completed chunks are kept in memory instead of being written to disk.
+    pub fn is_in_memory(&self) -> bool {
+        matches!(self.storage, ChunkStorage::Memory(_))
+    }
+
+    /// Close the writer.
+    ///
+    /// Any thread waiting for a chunk to be written or sleeping in [`sleep_unless_closed`] is woken
+    /// up. Closing the writer doesn't write anything, use [`finish`] to close the writer and write
+    /// out the rest of the recording.
+    ///
+    /// [`sleep_unless_closed`]: fn@Self::sleep_unless_closed
+    /// [`finish`]: fn@Self::finish
     pub fn close(&self) {
-        self.closed.store(true, atomic::Ordering::SeqCst);
+        *self.closed.lock().expect("closed poisoned") = true;
+        self.closed_condvar.notify_all();
 
         let mut notifiers = self
             .notifiers
@@ -94,7 +549,61 @@ impl ChunkedWriter {
     }
 
     pub fn is_closed(&self) -> bool {
-        self.closed.load(atomic::Ordering::SeqCst)
+        *self.closed.lock().expect("closed poisoned")
+    }
+
+    /// Sleep for `duration`, waking up early if the writer is closed.
+    ///
+    /// This should be used by a writer loop to wait between calls to [`write_completed_chunks`],
+    /// so that the loop can exit promptly when the writer is closed.
+    ///
+    /// [`write_completed_chunks`]: fn@Self::write_completed_chunks
+    pub fn sleep_unless_closed(&self, duration: Duration) {
+        let closed = self.closed.lock().expect("closed poisoned");
+        let _guard = self
+            .closed_condvar
+            .wait_timeout_while(closed, duration, |closed| !*closed)
+            .expect("closed poisoned");
+    }
+
+    /// Close the writer and write out the rest of the recording.
+    ///
+    /// All the chunks which haven't been written yet are written, together with any remaining
+    /// callsites. Then, for a recording on disk, the end file (`end.rfr`) is written to mark the
+    /// recording as finished. If any chunks couldn't be written, the end file marks the recording
+    /// as unclean and the error is returned. Calling this method again has no effect.
+    ///
+    /// This should be called after the writer loop has exited, otherwise it may still be writing
+    /// chunks.
+    pub fn finish(&self) -> Result<(), FinishError> {
+        self.close();
+        if self.finished.swap(true, atomic::Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // The end file is written even if some chunks couldn't be, so that readers can tell a
+        // recording with missing chunks from one which ended abruptly.
+        let result = self.write_all_chunks();
+        let stats = self.stats();
+        let clean = result.is_ok() && stats.write_errors == 0;
+
+        if let ChunkStorage::Disk { root_dir, .. } = &self.storage {
+            // A partially written end file would make the recording unreadable.
+            let end = ChunkedEnd::new(stats, clean);
+            write_atomically(&root_dir.join("end.rfr"), |writer| {
+                postcard::to_io(&end, writer)
+                    .map(|_| ())
+                    .map_err(WriteError::Serialization)
+            })
+            .map_err(|err| FinishError::WriteEndFailed(err.into_write_error()))?;
+        }
+
+        result.map_err(FinishError::WriteChunksFailed)
+    }
+
+    /// Returns a snapshot of the writer's statistics.
+    pub fn stats(&self) -> WriterStats {
+        self.counters.snapshot()
     }
 
     fn write_meta(base_dir: &Path, meta: &ChunkedMeta) -> Result<(), NewMetaError> {
@@ -112,42 +621,30 @@ impl ChunkedWriter {
         Ok(())
     }
 
-    fn ensure_dir(&self, time: &AbsTimestampSecs) {
-        fs::create_dir_all(self.dir_path(time)).unwrap();
-    }
-
-    fn dir_path(&self, time: &AbsTimestampSecs) -> PathBuf {
-        let ts = Timestamp::from_second(time.secs as i64).unwrap();
-        let ts_utc = ts.to_zoned(TimeZone::UTC);
-        self.dir_path_from_utc(&ts_utc)
-    }
-
-    fn dir_path_from_utc(&self, ts_utc: &Zoned) -> PathBuf {
-        Path::new(&self.root_dir)
-            .join(format!("{}", ts_utc.strftime("%Y-%m")))
-            .join(format!("{}", ts_utc.strftime("%d-%H")))
-    }
-
-    fn chunk_path(&self, time: &AbsTimestampSecs) -> PathBuf {
-        let ts = Timestamp::from_second(time.secs as i64).unwrap();
-        let ts_utc = ts.to_zoned(TimeZone::UTC);
-
-        self.dir_path_from_utc(&ts_utc)
-            .join(format!("chunk-{}.rfr", ts_utc.strftime("%M-%S")))
-    }
-
-    pub fn register_callsite(&self, callsite: Callsite) {
+    /// Register a callsite to be written to the recording.
+    ///
+    /// Returns the Id that the callsite was registered with, which must be used in records for
+    /// this callsite. This is the callsite's own Id, unless a different callsite has already been
+    /// registered with that Id. In that case the Id is rehashed (see [`CallsiteId::rehash`]) until
+    /// it no longer collides.
+    pub fn register_callsite(&self, mut callsite: Callsite) -> CallsiteId {
         let mut callsites_writer = self
             .callsites_writer
             .lock()
             .expect("callsite writer lock poisoned");
         // TODO(hds): Should we try to avoid building a `Callsite` if it's going to be a duplicate?
-        callsites_writer.push_callsite(callsite);
+        loop {
+            let callsite_id = callsite.callsite_id;
+            match callsites_writer.push_callsite(callsite.clone()) {
+                PushCallsiteResult::Added | PushCallsiteResult::Duplicate => return callsite_id,
+                PushCallsiteResult::Collision => callsite.callsite_id = callsite_id.rehash(),
+            }
+        }
     }
 
-    pub fn with_seq_chunk_buffer<F>(&self, timestamp: AbsTimestamp, f: F)
+    pub fn with_seq_chunk_buffer<F, R>(&self, timestamp: AbsTimestamp, f: F) -> R
     where
-        F: FnOnce(&SeqChunkBuffer),
+        F: FnOnce(&SeqChunkBuffer) -> R,
     {
         thread_local! {
             pub static SEQ_CHUNK_BUFFER: RefCell<Option<Arc<SeqChunkBuffer>>>
@@ -156,8 +653,16 @@ impl ChunkedWriter {
 
         SEQ_CHUNK_BUFFER.with_borrow_mut(|seq_chunk_buffer| {
             let current_buffer = self.current_seq_chunk_buffer(seq_chunk_buffer, timestamp.clone());
-            f(current_buffer);
-        });
+            let end_micros = as_micros(&current_buffer.interval().abs_end_time());
+            if end_micros
+                <= self
+                    .latest_written_end_micros
+                    .load(atomic::Ordering::Relaxed)
+            {
+                self.counters.late_record();
+            }
+            f(current_buffer)
+        })
     }
 
     fn current_seq_chunk_buffer<'a>(
@@ -175,6 +680,10 @@ impl ChunkedWriter {
         if seq_chunk_buffer.interval() != &interval {
             // Stored sequence chunk is not for this interval, create a new sequence chunk.
             *seq_chunk_buffer = self.create_seq_chunk_buffer(interval);
+        } else if Arc::strong_count(seq_chunk_buffer) == 1 {
+            // The chunk has already been written and discarded, so nothing else holds the stored
+            // sequence chunk. Create a new one so that the record is written to the chunk again.
+            *seq_chunk_buffer = self.create_seq_chunk_buffer(interval);
         }
 
         seq_chunk_buffer
@@ -183,13 +692,13 @@ impl ChunkedWriter {
     fn create_seq_chunk_buffer(&self, interval: ChunkInterval) -> Arc<SeqChunkBuffer> {
         let mut chunk_buffers = self.chunk_buffers.lock().expect("poisoned");
         let chunk_buffer = chunk_buffers
-            .iter_mut()
+            .iter()
             .find(|cb| cb.header.interval == interval);
         match chunk_buffer {
-            Some(chunk_buffer) => chunk_buffer.new_seq_chunk_buffer(),
+            Some(chunk_buffer) => chunk_buffer.new_seq_chunk_buffer(&self.counters),
             None => {
-                let mut new_chunk_buffer = ChunkBuffer::new(interval.clone());
-                let seq_chunk_buffer = new_chunk_buffer.new_seq_chunk_buffer();
+                let new_chunk_buffer = Arc::new(ChunkBuffer::new(interval.clone()));
+                let seq_chunk_buffer = new_chunk_buffer.new_seq_chunk_buffer(&self.counters);
                 chunk_buffers.push(new_chunk_buffer);
                 seq_chunk_buffer
             }
@@ -199,10 +708,12 @@ impl ChunkedWriter {
     /// Write all the completed chunks out to disk.
     ///
     /// A buffer period between now and the end of each chunk's interval is put in place to give
-    /// other threads time to finish writing to the sequence chunks. The buffer is on the order of
-    /// 100 milliseconds.
+    /// other threads time to finish writing to the sequence chunks. The buffer is the configured
+    /// write delay (see [`ChunkedWriterBuilder::write_delay`]).
     ///
-    /// Once each chunk is written to disk, it is discarded.
+    /// Once each chunk is written to disk, it is discarded. This is also the case if writing the
+    /// chunk fails, the error is returned once all the completed chunks have been processed. If
+    /// more than one chunk fails, the first error is returned.
     ///
     /// This method is still not race-condition safe, despite the buffer. If a thread is taking a
     /// very long time to prepare an even before calling [`with_seq_chunk_buffer`], then a record
@@ -212,22 +723,34 @@ impl ChunkedWriter {
     /// to the current time.
     pub fn write_completed_chunks(&self) -> Result<Duration, WriteChunksError> {
         let mut chunk_buffers = self.chunk_buffers.lock().expect("poisoned");
-        let write_time_buffer = Duration::from_millis(150);
-        // Tell the caller to check back an extra 50 milliseconds after we would be ready to write
-        // the next interval.
-        let next_write_buffer = write_time_buffer + Duration::from_millis(50);
+        let next_write_at_micros = self.next_write_at_micros.load(atomic::Ordering::Relaxed);
+        let now_micros = as_micros(&AbsTimestamp::now());
+        // The writer loop may also be woken early for a checkpoint, that isn't measured.
+        if next_write_at_micros > 0 && now_micros >= next_write_at_micros {
+            let latency = now_micros - next_write_at_micros;
+            self.counters
+                .writer_loop_latency(Duration::from_micros(latency));
+        }
+        self.observe_clock();
+        let write_time_buffer = self.write_delay;
+        // Tell the caller to check back a little after we would be ready to write the next
+        // interval.
+        let next_write_buffer = write_time_buffer + self.write_slack;
 
         self.flush_callsites();
 
+        let mut result = Ok(());
         chunk_buffers.retain(|chunk_buffer| {
             let end_time = chunk_buffer.header.interval.abs_end_time();
             let since_completion = AbsTimestamp::now()
                 .as_duration_since_epoch()
                 .saturating_sub(end_time.as_duration_since_epoch());
             if since_completion > write_time_buffer {
-                let writer = self.writer_for_chunk(chunk_buffer);
-                // TODO(hds): Check for errors
-                chunk_buffer.write(writer);
+                if let Err(err) = self.write_chunk(chunk_buffer)
+                    && result.is_ok()
+                {
+                    result = Err(err);
+                }
 
                 self.notifiers
                     .lock()
@@ -246,13 +769,104 @@ impl ChunkedWriter {
 
         // TODO(hds): Flush the callsites again afterwards to ensure consistency?
 
+        // Checkpoints are written without holding the lock, so that threads which are recording
+        // aren't blocked on creating new sequence chunks in the meantime.
+        let in_progress: Vec<_> = chunk_buffers.iter().cloned().collect();
+        drop(chunk_buffers);
+
+        self.apply_retention();
+        result?;
+        let until_checkpoint = self.checkpoint_if_due(&in_progress)?;
+
         let now = AbsTimestamp::now();
         let interval =
             ChunkInterval::from_timestamp_and_period(now.clone(), self.chunk_period_micros as u64);
 
-        let next_write_in = (interval.abs_end_time().as_duration_since_epoch() + next_write_buffer)
-            .saturating_sub(now.as_duration_since_epoch());
-        Ok(next_write_in)
+        let next_write_at = interval.abs_end_time().as_duration_since_epoch() + next_write_buffer;
+        self.next_write_at_micros
+            .store(next_write_at.as_micros() as u64, atomic::Ordering::Relaxed);
+        let next_write_in = next_write_at.saturating_sub(now.as_duration_since_epoch());
+        Ok(match until_checkpoint {
+            Some(until_checkpoint) => next_write_in.min(until_checkpoint),
+            None => next_write_in,
+        })
+    }
+
+    /// Write partial chunk files for the chunks still being recorded to, if a checkpoint is due.
+    ///
+    /// Returns the time until the next checkpoint is due, if checkpoints are enabled.
+    ///
+    /// A failed checkpoint is counted separately from failed chunk writes, as the complete chunk
+    /// may still be written successfully later.
+    fn checkpoint_if_due(
+        &self,
+        chunk_buffers: &[Arc<ChunkBuffer>],
+    ) -> Result<Option<Duration>, WriteChunksError> {
+        let (Some(checkpoint_interval), ChunkStorage::Disk { root_dir, .. }) =
+            (self.checkpoint_interval, &self.storage)
+        else {
+            return Ok(None);
+        };
+
+        let now = Instant::now();
+        {
+            let mut last_checkpoint = self.last_checkpoint.lock().expect("poisoned");
+            if let Some(last_checkpoint) = *last_checkpoint {
+                let since_checkpoint = now.saturating_duration_since(last_checkpoint);
+                if since_checkpoint < checkpoint_interval {
+                    return Ok(Some(checkpoint_interval - since_checkpoint));
+                }
+            }
+            *last_checkpoint = Some(now);
+        }
+
+        let mut result = Ok(Some(checkpoint_interval));
+        for chunk_buffer in chunk_buffers {
+            if let Err(err) = self.checkpoint_chunk(root_dir, chunk_buffer) {
+                self.counters.checkpoint_error();
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    }
+
+    fn checkpoint_chunk(
+        &self,
+        root_dir: &Path,
+        chunk: &ChunkBuffer,
+    ) -> Result<(), WriteChunksError> {
+        let _files = chunk.lock_files();
+        let record_count = chunk.record_count();
+        if record_count == chunk.checkpointed_records.load(atomic::Ordering::Relaxed) {
+            // Nothing new since the last checkpoint.
+            return Ok(());
+        }
+
+        self.take_clock_adjustments(chunk);
+        let interval = &chunk.header.interval;
+        self.layout
+            .ensure_dir(root_dir, &interval.base_time)
+            .map_err(WriteChunksError::CreateDirFailed)?;
+        // Readers ignore a partial chunk if the complete chunk exists. So if it does (because the
+        // chunk has already been written, or this chunk holds late records), the complete chunk is
+        // written instead. The chunk buffer contains everything in the complete chunk, so nothing
+        // is lost.
+        let complete_path = self.complete_chunk_path(root_dir, chunk);
+        let path = if complete_path.exists() || chunk.separate_path().is_some() {
+            complete_path
+        } else {
+            self.layout.partial_chunk_path(root_dir, interval)
+        };
+        let size = write_atomically(&path, |writer| chunk.write(writer))?;
+        chunk
+            .checkpointed_records
+            .store(record_count, atomic::Ordering::Relaxed);
+        self.track_chunk_file(path, interval, size);
+
+        Ok(())
     }
 
     /// Write all stored chunks to disk.
@@ -260,18 +874,29 @@ impl ChunkedWriter {
     /// The chunks are not discarded after being written. If further records are written to the
     /// contained sequence chunks, then they can be written to disk at a later time with subsequent
     /// calls to [`write_completed_chunks`] or [`write_all_chunks`].
-    pub fn write_all_chunks(&self) {
+    ///
+    /// All chunks are written, even if writing one of them fails. If more than one chunk fails,
+    /// the first error is returned.
+    pub fn write_all_chunks(&self) -> Result<(), WriteChunksError> {
         // Flush the callsites first
         self.flush_callsites();
+        self.observe_clock();
 
         let chunk_buffers = self.chunk_buffers.lock().expect("poisoned");
 
-        chunk_buffers.iter().for_each(|chunk_buffer| {
-            let writer = self.writer_for_chunk(chunk_buffer);
-            chunk_buffer.write(writer);
-        });
+        let mut result = Ok(());
+        for chunk_buffer in chunk_buffers.iter() {
+            if let Err(err) = self.write_chunk(chunk_buffer)
+                && result.is_ok()
+            {
+                result = Err(err);
+            }
+        }
 
         // TODO(hds): Flush the callsites again afterwards to ensure consistency?
+
+        self.apply_retention();
+        result
     }
 
     /// Wait for the current active chunk to be written to disk.
@@ -295,8 +920,297 @@ impl ChunkedWriter {
         }
     }
 
-    fn writer_for_chunk(&self, chunk: &ChunkBuffer) -> impl io::Write {
-        fs::File::create(self.chunk_path(&chunk.header.interval.base_time)).unwrap()
+    /// Dump the chunks kept in memory to a new chunked recording at `root_dir`.
+    ///
+    /// The dumped recording contains all the completed chunks in the ring as well as the chunks
+    /// which are still being recorded to. The chunks are not discarded, so the same chunks may be
+    /// dumped again later.
+    ///
+    /// # Errors
+    ///
+    /// This method will fail if this writer doesn't keep chunks in memory (see
+    /// [`ChunkedWriterBuilder::build_in_memory`]), if there is already something at `root_dir`,
+    /// or if writing any of the recording's files fails.
+    pub fn dump_to<P>(&self, root_dir: P) -> Result<(), DumpError>
+    where
+        P: AsRef<Path>,
+    {
+        let ChunkStorage::Memory(ring) = &self.storage else {
+            return Err(DumpError::NotInMemory);
+        };
+        let root_dir = root_dir.as_ref();
+
+        if let Ok(true) = root_dir.try_exists() {
+            return Err(DumpError::AlreadyExists);
+        }
+
+        fs::create_dir_all(root_dir).map_err(DumpError::CreateRecordingDirFailed)?;
+        Self::write_meta(root_dir, &self.meta).map_err(|err| match err {
+            NewMetaError::AlreadyExists => DumpError::AlreadyExists,
+            NewMetaError::WriteFailed(inner) => DumpError::WriteMetaFailed(inner),
+        })?;
+
+        {
+            let callsites_writer = self
+                .callsites_writer
+                .lock()
+                .expect("callsite writer lock poisoned");
+            let callsites_file = fs::File::create(root_dir.join("callsites.rfr"))
+                .map_err(|err| DumpError::WriteCallsitesFailed(WriteError::Io(err)))?;
+            callsites_writer
+                .chunked_callsites()
+                .to_io(callsites_file)
+                .map_err(|err| DumpError::WriteCallsitesFailed(WriteError::Io(err)))?;
+        }
+
+        // Lock the chunk buffers before the ring (as `write_completed_chunks` does), so that no
+        // chunk can move from one to the other while we're collecting them.
+        let chunk_buffers = self.chunk_buffers.lock().expect("poisoned");
+        let ring = ring.lock().expect("chunk ring poisoned");
+        let in_progress = chunk_buffers
+            .iter()
+            .map(|chunk_buffer| {
+                let mut data = Vec::new();
+                chunk_buffer
+                    .write(&mut data)
+                    .map_err(DumpError::WriteChunkFailed)?;
+                Ok((chunk_buffer.header.interval.clone(), data))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (interval, data) in ring.chunks.iter().cloned().chain(in_progress) {
+            self.layout
+                .ensure_dir(root_dir, &interval.base_time)
+                .map_err(|err| DumpError::WriteChunkFailed(WriteError::Io(err)))?;
+            let path = self.layout.chunk_path(root_dir, &interval);
+            write_atomically(&path, |writer| {
+                io::Write::write_all(writer, &data).map_err(WriteError::Io)
+            })
+            .map_err(|err| DumpError::WriteChunkFailed(err.into_write_error()))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_chunk(&self, chunk: &ChunkBuffer) -> Result<(), WriteChunksError> {
+        self.take_clock_adjustments(chunk);
+        match self.try_write_chunk(chunk) {
+            Ok(bytes) => {
+                self.counters.chunk_written(bytes);
+                self.latest_written_end_micros.fetch_max(
+                    as_micros(&chunk.header.interval.abs_end_time()),
+                    atomic::Ordering::Relaxed,
+                );
+                Ok(())
+            }
+            Err(err) => {
+                self.counters.write_error();
+                Err(err)
+            }
+        }
+    }
+
+    /// Write a chunk, returning the size of the serialized chunk in bytes.
+    fn try_write_chunk(&self, chunk: &ChunkBuffer) -> Result<u64, WriteChunksError> {
+        let (root_dir, retention, written_chunks) = match &self.storage {
+            ChunkStorage::Disk {
+                root_dir,
+                retention,
+                written_chunks,
+            } => (root_dir, retention, written_chunks),
+            ChunkStorage::Memory(ring) => {
+                let mut ring = ring.lock().expect("chunk ring poisoned");
+                if !chunk.is_written()
+                    && let Some(data) = ring.get(&chunk.header.interval)
+                {
+                    let existing = read_chunk_from_io(io::Cursor::new(data))
+                        .map_err(|_| WriteChunksError::ReadExistingChunkFailed)?;
+                    chunk.merge_existing(existing);
+                }
+                let mut data = Vec::new();
+                chunk
+                    .write(&mut data)
+                    .map_err(WriteChunksError::WriteChunkFailed)?;
+                chunk.set_written();
+                let size = data.len() as u64;
+                ring.push(chunk.header.interval.clone(), data);
+                return Ok(size);
+            }
+        };
+
+        let _files = chunk.lock_files();
+        let interval = &chunk.header.interval;
+        // The directory may not exist yet, or may have been removed by `apply_retention`.
+        self.layout
+            .ensure_dir(root_dir, &interval.base_time)
+            .map_err(WriteChunksError::CreateDirFailed)?;
+        let path = self.complete_chunk_path(root_dir, chunk);
+        let size = write_atomically(&path, |writer| chunk.write(writer))?;
+        chunk.set_written();
+        self.track_chunk_file(path, interval, size);
+
+        // The complete chunk supersedes the checkpoint, which may not exist.
+        let partial_path = self.layout.partial_chunk_path(root_dir, interval);
+        _ = fs::remove_file(&partial_path);
+        if !retention.is_unlimited() {
+            written_chunks
+                .lock()
+                .expect("poisoned")
+                .retain(|wc| wc.path != partial_path);
+        }
+
+        Ok(size)
+    }
+
+    /// Keep track of a chunk file (complete, partial, or late) which has been written, so that it
+    /// can be deleted by [`apply_retention`].
+    ///
+    /// [`apply_retention`]: fn@Self::apply_retention
+    fn track_chunk_file(&self, path: PathBuf, interval: &ChunkInterval, size: u64) {
+        let ChunkStorage::Disk {
+            retention,
+            written_chunks,
+            ..
+        } = &self.storage
+        else {
+            return;
+        };
+        if retention.is_unlimited() {
+            return;
+        }
+
+        let mut written_chunks = written_chunks.lock().expect("poisoned");
+        if let Some(written_chunk) = written_chunks.iter_mut().find(|wc| wc.path == path) {
+            // The file has been rewritten (e.g. by `write_all_chunks` or a later checkpoint).
+            written_chunk.size = size;
+        } else {
+            written_chunks.push_back(WrittenChunk {
+                path,
+                start_time: interval.abs_start_time(),
+                end_time: interval.abs_end_time(),
+                size,
+            });
+            written_chunks
+                .make_contiguous()
+                .sort_by(|a, b| a.start_time.cmp(&b.start_time));
+        }
+    }
+
+    /// Returns the path that the complete chunk for `chunk` is written to.
+    ///
+    /// A chunk buffer which hasn't been written yet, but whose chunk file already exists, holds
+    /// late records for a chunk which was written and discarded. The existing chunk is merged into
+    /// the chunk buffer, so that writing it replaces the existing chunk without losing anything. If
+    /// the existing chunk can't be read, the late records are written to a separate chunk file
+    /// instead. A chunk buffer which has been written before already contains everything in its
+    /// chunk file, so that file is simply replaced.
+    fn complete_chunk_path(&self, root_dir: &Path, chunk: &ChunkBuffer) -> PathBuf {
+        if let Some(separate_path) = chunk.separate_path() {
+            return separate_path;
+        }
+
+        let interval = &chunk.header.interval;
+        let path = self.layout.chunk_path(root_dir, interval);
+        if chunk.is_written() || chunk.has_existing() || !path.exists() {
+            return path;
+        }
+
+        let existing = fs::File::open(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| read_chunk_from_io(file).map_err(|err| err.to_string()));
+        match existing {
+            Ok(existing) => {
+                chunk.merge_existing(existing);
+                path
+            }
+            Err(err) => {
+                // Replacing the existing chunk would lose whatever can still be read from it.
+                let separate_path = self.layout.separate_chunk_path(root_dir, interval);
+                eprintln!(
+                    "Failed to read chunk {path} to merge late records, writing them to \
+                    {separate_path} instead: {err}",
+                    path = path.display(),
+                    separate_path = separate_path.display(),
+                );
+                chunk.set_separate_path(separate_path.clone());
+                separate_path
+            }
+        }
+    }
+
+    /// Delete the oldest chunks until the recording is within the configured [`Retention`].
+    ///
+    /// All the files for a chunk's interval (the complete chunk, a partial chunk, and any chunks
+    /// of late records) are deleted together.
+    fn apply_retention(&self) {
+        let ChunkStorage::Disk {
+            root_dir,
+            retention,
+            written_chunks,
+        } = &self.storage
+        else {
+            return;
+        };
+        let mut written_chunks = written_chunks.lock().expect("poisoned");
+        let Some(newest_end_time) = written_chunks.back().map(|wc| wc.end_time.clone()) else {
+            return;
+        };
+
+        let mut total_bytes: u64 = written_chunks.iter().map(|wc| wc.size).sum();
+        while let Some(oldest) = written_chunks.front() {
+            let exceeds_duration = retention.max_duration.is_some_and(|max_duration| {
+                newest_end_time
+                    .as_duration_since_epoch()
+                    .saturating_sub(oldest.start_time.as_duration_since_epoch())
+                    > max_duration
+            });
+            let exceeds_bytes = retention
+                .max_bytes
+                .is_some_and(|max_bytes| total_bytes > max_bytes);
+            if !exceeds_duration && !exceeds_bytes {
+                break;
+            }
+
+            let oldest_start_time = oldest.start_time.clone();
+            while written_chunks
+                .front()
+                .is_some_and(|wc| wc.start_time == oldest_start_time)
+            {
+                let oldest = written_chunks.pop_front().expect("front exists");
+                total_bytes -= oldest.size;
+                remove_chunk_file(root_dir, &oldest.path);
+            }
+        }
+    }
+
+    /// Compare the wall clock with the recording clock, keeping an adjustment if they have moved
+    /// apart since the last adjustment.
+    fn observe_clock(&self) {
+        let adjustment = ClockAdjustment::observe();
+        let mut clock = self.clock.lock().expect("clock poisoned");
+        if adjustment
+            .wall_clock_offset_micros
+            .abs_diff(clock.last_offset_micros)
+            >= CLOCK_ADJUSTMENT_THRESHOLD_MICROS
+        {
+            clock.last_offset_micros = adjustment.wall_clock_offset_micros;
+            clock.pending.push(adjustment);
+        }
+    }
+
+    /// Move the pending clock adjustments observed before the end of `chunk` into it.
+    fn take_clock_adjustments(&self, chunk: &ChunkBuffer) {
+        let end_time = chunk.header.interval.abs_end_time();
+        let mut clock = self.clock.lock().expect("clock poisoned");
+        let (adjustments, pending): (Vec<_>, Vec<_>) = clock
+            .pending
+            .drain(..)
+            .partition(|adjustment| adjustment.observed_at < end_time);
+        clock.pending = pending;
+        chunk
+            .clock_adjustments
+            .lock()
+            .expect("clock adjustments poisoned")
+            .extend(adjustments);
     }
 
     fn flush_callsites(&self) {
@@ -311,6 +1225,98 @@ impl ChunkedWriter {
     }
 }
 
+/// Write a file atomically, returning the number of bytes written.
+///
+/// The contents are written (buffered) to a temporary file next to `path`, which is synced to disk
+/// and then renamed to `path`. A reader will see either the previous file at `path` (if there was
+/// one) or the complete new file, never a partially written file. The temporary file doesn't end
+/// in `.rfr`, so readers will ignore it.
+fn write_atomically<F>(path: &Path, write: F) -> Result<u64, WriteChunksError>
+where
+    F: FnOnce(&mut CountingWriter<BufWriter<fs::File>>) -> Result<(), WriteError>,
+{
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let result = (|| {
+        let file = fs::File::create(&temp_path).map_err(WriteChunksError::CreateFileFailed)?;
+        let mut writer = CountingWriter::new(BufWriter::new(file));
+        write(&mut writer).map_err(WriteChunksError::WriteChunkFailed)?;
+        let count = writer.count;
+        let file = writer
+            .inner
+            .into_inner()
+            .map_err(|err| WriteChunksError::PersistFailed(err.into_error()))?;
+        file.sync_all().map_err(WriteChunksError::PersistFailed)?;
+        fs::rename(&temp_path, path).map_err(WriteChunksError::PersistFailed)?;
+        Ok(count)
+    })();
+    if result.is_err() {
+        // Don't leave a partial temporary file behind, it will be recreated on the next attempt.
+        _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Counts the bytes written to the inner writer.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: io::Write> io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn as_micros(timestamp: &AbsTimestamp) -> u64 {
+    timestamp.as_duration_since_epoch().as_micros() as u64
+}
+
+fn to_utc(time: &AbsTimestampSecs) -> Zoned {
+    let ts = Timestamp::from_second(time.secs as i64).unwrap();
+    ts.to_zoned(TimeZone::UTC)
+}
+
+fn dir_path_from_utc(root_dir: &Path, ts_utc: &Zoned) -> PathBuf {
+    root_dir
+        .join(format!("{}", ts_utc.strftime("%Y-%m")))
+        .join(format!("{}", ts_utc.strftime("%d-%H")))
+}
+
+/// Remove a chunk file, together with the directories containing it if they are now empty.
+fn remove_chunk_file(root_dir: &Path, path: &Path) {
+    if let Err(err) = fs::remove_file(path)
+        && err.kind() != io::ErrorKind::NotFound
+    {
+        eprintln!("Failed to remove chunk {}: {err}", path.display());
+        return;
+    }
+
+    // Remove the `<day>-<hour>` and `<year>-<month>` directories if they're empty. Removing a
+    // directory which isn't empty fails, which is what we want.
+    for dir in path.ancestors().skip(1).take(2) {
+        if dir == root_dir || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
 /// Error waiting for a chunk to be written
 #[derive(Debug, Clone, Copy)]
 pub enum WaitForWriteError {
@@ -417,6 +1423,8 @@ pub enum NewChunkedWriterError {
     WriteMetaFailed(WriteError),
     /// There was a failure writing the callsites file
     WriteCallsitesFailed(WriteError),
+    /// The chunk period is neither a whole number of seconds nor a divisor of a second
+    InvalidChunkPeriod(Duration),
 }
 
 impl fmt::Display for NewChunkedWriterError {
@@ -430,6 +1438,10 @@ impl fmt::Display for NewChunkedWriterError {
             Self::WriteCallsitesFailed(inner) => {
                 write!(f, "failed to write `callsites.rfr` file: {inner}")
             }
+            Self::InvalidChunkPeriod(period) => write!(
+                f,
+                "invalid chunk period {period:?}: must be a whole number of seconds or divide a second evenly"
+            ),
         }
     }
 }
@@ -489,58 +1501,270 @@ impl fmt::Display for WriteError {
 
 impl error::Error for WriteError {}
 
+/// An error occurring when dumping an in-memory recording to disk.
+#[derive(Debug)]
+pub enum DumpError {
+    /// The writer doesn't keep chunks in memory, so there is nothing to dump.
+    NotInMemory,
+    /// There is already something at the location to dump to
+    AlreadyExists,
+    /// Could not create the directory for the chunked recording
+    CreateRecordingDirFailed(io::Error),
+    /// There was a failure writing the meta file
+    WriteMetaFailed(WriteError),
+    /// There was a failure writing the callsites file
+    WriteCallsitesFailed(WriteError),
+    /// There was a failure writing a chunk file
+    WriteChunkFailed(WriteError),
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInMemory => write!(f, "the chunked writer doesn't keep chunks in memory"),
+            Self::AlreadyExists => write!(f, "there is already something at this location"),
+            Self::CreateRecordingDirFailed(inner) => {
+                write!(f, "recording directory could not be created: {inner}")
+            }
+            Self::WriteMetaFailed(inner) => write!(f, "failed to write `meta.rfr`: {inner}"),
+            Self::WriteCallsitesFailed(inner) => {
+                write!(f, "failed to write `callsites.rfr` file: {inner}")
+            }
+            Self::WriteChunkFailed(inner) => write!(f, "failed to write chunk file: {inner}"),
+        }
+    }
+}
+impl error::Error for DumpError {}
+
+/// An error occurring when writing chunks.
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum WriteChunksError {
-    FileOpenFailed,
+    /// Could not create the directory for a chunk file
+    CreateDirFailed(io::Error),
+    /// Could not create a chunk file
+    CreateFileFailed(io::Error),
+    /// There was a failure writing the contents of a chunk
+    WriteChunkFailed(WriteError),
+    /// The written chunk file could not be synced to disk or moved into place
+    PersistFailed(io::Error),
+    /// A chunk which was already written could not be read to merge late records into it
+    ReadExistingChunkFailed,
+}
+
+impl fmt::Display for WriteChunksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateDirFailed(inner) => {
+                write!(f, "chunk directory could not be created: {inner}")
+            }
+            Self::CreateFileFailed(inner) => write!(f, "chunk file could not be created: {inner}"),
+            Self::WriteChunkFailed(inner) => write!(f, "failed to write chunk: {inner}"),
+            Self::PersistFailed(inner) => {
+                write!(
+                    f,
+                    "chunk file could not be synced and moved into place: {inner}"
+                )
+            }
+            Self::ReadExistingChunkFailed => {
+                write!(f, "existing chunk could not be read to merge late records")
+            }
+        }
+    }
+}
+impl error::Error for WriteChunksError {}
+
+impl WriteChunksError {
+    fn into_write_error(self) -> WriteError {
+        match self {
+            Self::CreateDirFailed(inner)
+            | Self::CreateFileFailed(inner)
+            | Self::PersistFailed(inner) => WriteError::Io(inner),
+            Self::WriteChunkFailed(inner) => inner,
+            Self::ReadExistingChunkFailed => WriteError::Io(io::Error::other(self.to_string())),
+        }
+    }
+}
+
+/// An error occurring when finishing a chunked recording.
+#[derive(Debug)]
+pub enum FinishError {
+    /// There was a failure writing the remaining chunks
+    WriteChunksFailed(WriteChunksError),
+    /// There was a failure writing the end file
+    WriteEndFailed(WriteError),
+}
+
+impl fmt::Display for FinishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WriteChunksFailed(inner) => write!(f, "failed to write chunks: {inner}"),
+            Self::WriteEndFailed(inner) => write!(f, "failed to write `end.rfr`: {inner}"),
+        }
+    }
+}
+impl error::Error for FinishError {}
+
+/// Clock adjustments observed by the writer which haven't been stored in a chunk yet.
+#[derive(Debug, Default)]
+struct ClockObserver {
+    /// The wall clock offset of the last adjustment, the clocks start out in agreement.
+    last_offset_micros: i64,
+    pending: Vec<ClockAdjustment>,
 }
 
 #[derive(Debug)]
 pub struct ChunkBuffer {
     header: ChunkHeader,
 
-    seq_chunks: Vec<Arc<SeqChunkBuffer>>,
+    seq_chunks: Mutex<Vec<Arc<SeqChunkBuffer>>>,
+    /// Held while this chunk's files are being written, so that a checkpoint and a write of the
+    /// same chunk can't interleave.
+    files: Mutex<()>,
+    /// Whether this chunk has been written at least once.
+    written: AtomicBool,
+    /// The number of records in this chunk when it was last checkpointed.
+    checkpointed_records: AtomicUsize,
+    /// A previously written chunk for the same interval, whose sequence chunks are written
+    /// together with this chunk's own.
+    existing: Mutex<Option<Chunk>>,
+    /// The path this chunk is written to if the previously written chunk for the same interval
+    /// couldn't be read.
+    separate_path: Mutex<Option<PathBuf>>,
+    /// Clock adjustments observed up until the end of this chunk.
+    clock_adjustments: Mutex<Vec<ClockAdjustment>>,
 }
 
 impl ChunkBuffer {
     fn new(interval: ChunkInterval) -> Self {
         Self {
             header: ChunkHeader::new(interval),
-            seq_chunks: Vec::new(),
+            seq_chunks: Mutex::new(Vec::new()),
+            files: Mutex::new(()),
+            written: AtomicBool::new(false),
+            checkpointed_records: AtomicUsize::new(0),
+            existing: Mutex::new(None),
+            separate_path: Mutex::new(None),
+            clock_adjustments: Mutex::new(Vec::new()),
         }
     }
 
-    fn new_seq_chunk_buffer(&mut self) -> Arc<SeqChunkBuffer> {
-        let seq_chunk_buffer = Arc::new(SeqChunkBuffer::new(self.header.interval.clone()));
-        self.seq_chunks.push(Arc::clone(&seq_chunk_buffer));
-        seq_chunk_buffer
+    fn is_written(&self) -> bool {
+        self.written.load(atomic::Ordering::Acquire)
     }
 
-    fn write(&self, writer: impl io::Write) {
-        let mut writer = writer;
+    fn set_written(&self) {
+        self.written.store(true, atomic::Ordering::Release);
+    }
+
+    fn lock_files(&self) -> MutexGuard<'_, ()> {
+        self.files.lock().expect("chunk files poisoned")
+    }
+
+    fn record_count(&self) -> usize {
+        self.seq_chunks
+            .lock()
+            .expect("sequence chunks poisoned")
+            .iter()
+            .map(|seq_chunk| seq_chunk.record_count())
+            .sum()
+    }
+
+    fn merge_existing(&self, existing: Chunk) {
+        *self.existing.lock().expect("existing chunk poisoned") = Some(existing);
+    }
+
+    fn has_existing(&self) -> bool {
+        self.existing
+            .lock()
+            .expect("existing chunk poisoned")
+            .is_some()
+    }
+
+    fn separate_path(&self) -> Option<PathBuf> {
+        self.separate_path
+            .lock()
+            .expect("separate path poisoned")
+            .clone()
+    }
+
+    fn set_separate_path(&self, path: PathBuf) {
+        *self.separate_path.lock().expect("separate path poisoned") = Some(path);
+    }
 
-        postcard::to_io(&current_software_version(), &mut writer).unwrap();
+    fn new_seq_chunk_buffer(&self, counters: &Arc<WriterCounters>) -> Arc<SeqChunkBuffer> {
+        let seq_chunk_buffer = Arc::new(SeqChunkBuffer::new_with_counters(
+            self.header.interval.clone(),
+            Arc::clone(counters),
+        ));
+        self.seq_chunks
+            .lock()
+            .expect("sequence chunks poisoned")
+            .push(Arc::clone(&seq_chunk_buffer));
+        seq_chunk_buffer
+    }
 
-        let (earliest_timestamp, latest_timestamp) = self
+    /// Write the chunk, including the sequence chunks of the existing chunk for the same interval
+    /// (if there is one) before this chunk's own.
+    fn write(&self, writer: impl io::Write) -> Result<(), WriteError> {
+        let mut writer = writer;
+        let existing = self.existing.lock().expect("existing chunk poisoned");
+        let existing = existing.as_ref();
+        // New sequence chunks may be added while we're writing, they'll be in the next write.
+        let seq_chunks = self
             .seq_chunks
+            .lock()
+            .expect("sequence chunks poisoned")
+            .clone();
+
+        postcard::to_io(&current_software_version(), &mut writer)
+            .map_err(WriteError::Serialization)?;
+
+        let (initial_earliest, initial_latest) = match existing {
+            Some(existing) => (
+                existing.header.earliest_timestamp,
+                existing.header.latest_timestamp,
+            ),
+            None => (self.header.earliest_timestamp, self.header.latest_timestamp),
+        };
+        let (earliest_timestamp, latest_timestamp) = seq_chunks
             .iter()
             .map(|seq_chunk| (seq_chunk.earliest_timestamp(), seq_chunk.latest_timestamp()))
             .fold(
-                (self.header.earliest_timestamp, self.header.latest_timestamp),
+                (initial_earliest, initial_latest),
                 |(acc_earliest, acc_latest), (earliest, latest)| {
                     (acc_earliest.min(earliest), acc_latest.max(latest))
                 },
             );
+        let clock_adjustments = existing
+            .map_or(&[][..], |existing| &existing.header.clock_adjustments)
+            .iter()
+            .chain(
+                self.clock_adjustments
+                    .lock()
+                    .expect("clock adjustments poisoned")
+                    .iter(),
+            )
+            .cloned()
+            .collect();
         let header = ChunkHeader {
             interval: self.header.interval.clone(),
             earliest_timestamp,
             latest_timestamp,
+            clock_adjustments,
         };
-        postcard::to_io(&header, &mut writer).unwrap();
+        postcard::to_io(&header, &mut writer).map_err(WriteError::Serialization)?;
 
-        postcard::to_io(&self.seq_chunks.len(), &mut writer).unwrap();
-        for seq_chunk in &self.seq_chunks {
-            seq_chunk.write(&mut writer);
+        let existing_seq_chunks = existing.map_or(&[][..], |existing| &existing.seq_chunks);
+        postcard::to_io(&(existing_seq_chunks.len() + seq_chunks.len()), &mut writer)
+            .map_err(WriteError::Serialization)?;
+        for seq_chunk in existing_seq_chunks {
+            postcard::to_io(seq_chunk, &mut writer).map_err(WriteError::Serialization)?;
         }
+        for seq_chunk in &seq_chunks {
+            seq_chunk.write(&mut writer)?;
+        }
+
+        Ok(())
     }
 }
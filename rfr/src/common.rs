@@ -1,6 +1,8 @@
 use std::{
     cmp::Ordering,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    fmt,
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use serde::{Deserialize, Serialize};
@@ -14,14 +16,14 @@ use crate::CallsiteId;
 pub struct AbsTimestamp {
     /// Whole seconds component of the timestamp, measured from the [`UNIX_EPOCH`].
     pub secs: u64,
-    /// Sub-second component of the timestamp, measured in microseconds.
-    pub subsec_micros: u32,
+    /// Sub-second component of the timestamp, measured in nanoseconds.
+    pub subsec_nanos: u32,
 }
 
 impl Ord for AbsTimestamp {
     fn cmp(&self, other: &Self) -> Ordering {
         match self.secs.cmp(&other.secs) {
-            Ordering::Equal => self.subsec_micros.cmp(&other.subsec_micros),
+            Ordering::Equal => self.subsec_nanos.cmp(&other.subsec_nanos),
             other => other,
         }
     }
@@ -37,7 +39,7 @@ impl From<Duration> for AbsTimestamp {
     fn from(value: Duration) -> Self {
         Self {
             secs: value.as_secs(),
-            subsec_micros: value.subsec_micros(),
+            subsec_nanos: value.subsec_nanos(),
         }
     }
 }
@@ -46,20 +48,76 @@ impl AbsTimestamp {
     /// Earliest measurable time
     pub const EARLIEST: Self = Self {
         secs: 0,
-        subsec_micros: 1,
+        subsec_nanos: 1,
     };
 
     /// Get an absolute timestamp representing the current time.
+    ///
+    /// The time is measured with a monotonic clock, which is anchored to the wall clock once per
+    /// process (see [`clock_anchor`]). Timestamps are therefore never affected by the wall clock
+    /// being adjusted, e.g. by NTP, but may drift from it over time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rfr::AbsTimestamp;
+    /// let earlier = AbsTimestamp::now();
+    /// let later = AbsTimestamp::now();
+    ///
+    /// assert!(AbsTimestamp::clock_anchor() <= earlier);
+    /// assert!(earlier <= later);
+    /// ```
+    ///
+    /// [`clock_anchor`]: fn@Self::clock_anchor
     pub fn now() -> Self {
-        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().into()
+        let anchor = clock_anchor();
+        (anchor.wall + anchor.instant.elapsed()).into()
+    }
+
+    /// The wall clock time at which the monotonic clock used by [`now`] was anchored.
+    ///
+    /// [`now`]: fn@Self::now
+    pub fn clock_anchor() -> Self {
+        clock_anchor().wall.into()
+    }
+
+    /// Get an absolute timestamp representing the current wall clock time.
+    ///
+    /// Unlike [`now`], this time may jump backwards or forwards when the system clock is adjusted.
+    ///
+    /// [`now`]: fn@Self::now
+    pub fn wall_clock_now() -> Self {
+        wall_clock_since_epoch().into()
     }
 
     /// Return the [`Duration`] since the UNIX epoch represented by this absolute timestamp.
     pub fn as_duration_since_epoch(&self) -> Duration {
-        Duration::new(self.secs, self.subsec_micros * 1_000)
+        Duration::new(self.secs, self.subsec_nanos)
+    }
+
+    /// The sub-second component of the timestamp in whole microseconds.
+    pub fn subsec_micros(&self) -> u32 {
+        self.subsec_nanos / 1_000
     }
 }
 
+struct ClockAnchor {
+    instant: Instant,
+    wall: Duration,
+}
+
+fn clock_anchor() -> &'static ClockAnchor {
+    static CLOCK_ANCHOR: OnceLock<ClockAnchor> = OnceLock::new();
+    CLOCK_ANCHOR.get_or_init(|| ClockAnchor {
+        instant: Instant::now(),
+        wall: wall_clock_since_epoch(),
+    })
+}
+
+fn wall_clock_since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+}
+
 /// The level of a span or event.
 ///
 /// The `tracing` levels are mapped as per the [Bunyan level suggestions].
@@ -120,6 +178,10 @@ pub enum FieldValue {
     Str(String),
 }
 
+/// The instrumentation defined identifier for an object.
+///
+/// An instrumentation Id is never reused within a recording, so it identifies a single object over
+/// its whole lifetime.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct InstrumentationId(u64);
 
@@ -135,6 +197,14 @@ impl InstrumentationId {
     }
 }
 
+/// A span object.
+///
+/// A span represents a period of time, within which the span may change from active to inactive
+/// multiple times during its lifetime.
+///
+/// The values of the fields named in the callsite's `split_field_names` are stored in the same
+/// order in `split_field_values`. If a value isn't available for every split field, then the
+/// split field values are left empty and all the values are stored in `dynamic_fields` instead.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Span {
     iid: InstrumentationId,
@@ -144,6 +214,50 @@ pub struct Span {
     dynamic_fields: Vec<Field>,
 }
 
+impl Span {
+    /// Create a new span object.
+    pub fn new(
+        iid: InstrumentationId,
+        callsite_id: CallsiteId,
+        parent: Parent,
+        split_field_values: Vec<FieldValue>,
+        dynamic_fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            iid,
+            callsite_id,
+            parent,
+            split_field_values,
+            dynamic_fields,
+        }
+    }
+
+    /// The instrumentation Id of this span.
+    pub fn iid(&self) -> InstrumentationId {
+        self.iid
+    }
+
+    /// The Id of the callsite that this span was created at.
+    pub fn callsite_id(&self) -> CallsiteId {
+        self.callsite_id
+    }
+
+    /// The parent of this span.
+    pub fn parent(&self) -> &Parent {
+        &self.parent
+    }
+
+    /// The values of the split fields, in the order of the callsite's `split_field_names`.
+    pub fn split_field_values(&self) -> &[FieldValue] {
+        &self.split_field_values
+    }
+
+    /// Any field values which aren't stored as split field values.
+    pub fn dynamic_fields(&self) -> &[Field] {
+        &self.dynamic_fields
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum Parent {
     Current,
@@ -151,6 +265,10 @@ pub enum Parent {
     Explicit { iid: InstrumentationId },
 }
 
+/// An event representing a moment in time.
+///
+/// The field values are stored in the same way as for a [`Span`], split field values are in the
+/// order of the callsite's `split_field_names` and any others are stored in `dynamic_fields`.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Event {
     pub callsite_id: CallsiteId,
@@ -183,7 +301,7 @@ pub enum TaskKind {
     Other(String),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Task {
     pub iid: InstrumentationId,
     pub callsite_id: CallsiteId,
@@ -192,6 +310,142 @@ pub struct Task {
     pub task_kind: TaskKind,
 
     pub context: Option<InstrumentationId>,
+
+    /// The location in the instrumented application's source code where the task was spawned.
+    pub location: Option<Location>,
+    /// Any fields on the spawn span which aren't stored elsewhere on the task.
+    pub fields: Vec<Field>,
+}
+
+impl Task {
+    /// A placeholder for a task which is referenced by a record, but whose object wasn't
+    /// available when the record was written.
+    ///
+    /// This happens, for example, when a waker is woken for a task that was spawned before the
+    /// recording started.
+    pub fn unknown(iid: InstrumentationId) -> Self {
+        Self {
+            iid,
+            callsite_id: CallsiteId::from(0),
+            task_id: TaskId::from(0),
+            task_name: "unknown task".into(),
+            task_kind: TaskKind::Other("unknown".into()),
+            context: None,
+            location: None,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// A location in the instrumented application's source code.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Location {
+    /// The path of the source file.
+    pub file: String,
+    /// The line number within the source file.
+    pub line: u32,
+    /// The column number within the line.
+    pub col: u32,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// The kind of a resource.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ResourceKind {
+    /// A timer, such as `Sleep`.
+    Timer,
+    /// A synchronization primitive, such as a `Mutex` or a `Semaphore`.
+    Sync,
+    /// Any other kind of resource.
+    Other(String),
+}
+
+/// A resource provided by the async runtime.
+///
+/// Resources are the primitives which tasks wait on, such as timers, synchronization primitives,
+/// and channels.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Resource {
+    pub iid: InstrumentationId,
+    pub callsite_id: CallsiteId,
+    /// The concrete type of the resource, e.g. `Sleep` or `Mutex`.
+    pub concrete_type: String,
+    pub kind: ResourceKind,
+    /// The location where the resource was created, if known.
+    pub location: Option<Location>,
+    /// Whether this resource is internal to another resource (e.g. the semaphore within a
+    /// mutex).
+    pub is_internal: bool,
+}
+
+/// An async operation on a resource.
+///
+/// An async op is a single operation, such as `Semaphore::acquire` or `Sleep::new`, which a task
+/// awaits on a [`Resource`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AsyncOp {
+    pub iid: InstrumentationId,
+    pub callsite_id: CallsiteId,
+    /// The method which created the async op, e.g. `Semaphore::acquire`.
+    pub source: String,
+    /// The instrumentation Id of the resource that this async op is operating on.
+    pub resource_iid: InstrumentationId,
+    /// The instrumentation Id of the task which was being polled when the async op was created.
+    pub context: Option<InstrumentationId>,
+}
+
+/// A poll operation on a resource.
+///
+/// A poll op describes a single poll of a resource (or an async op on that resource) and whether
+/// the resource was ready.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PollOp {
+    /// The instrumentation Id of the resource being polled.
+    pub resource_iid: InstrumentationId,
+    /// The instrumentation Id of the async op being polled, if the poll is part of one.
+    pub async_op_iid: Option<InstrumentationId>,
+    /// The name of the poll operation, e.g. `poll_acquire`.
+    pub op_name: String,
+    /// Whether the poll returned `Poll::Ready`.
+    pub is_ready: bool,
+    /// The instrumentation Id of the task which was being polled, if any.
+    pub context: Option<InstrumentationId>,
+}
+
+/// An update to an attribute of a resource or async op.
+///
+/// Examples of attributes are the number of permits available in a semaphore or the duration of
+/// a `Sleep`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StateUpdate {
+    /// The instrumentation Id of the resource or async op being updated.
+    pub iid: InstrumentationId,
+    /// The name of the attribute being updated.
+    pub attribute: FieldName,
+    /// The new value, or the difference to apply, depending on `op`.
+    pub value: FieldValue,
+    /// The unit of the value, e.g. `ms`, if one was given.
+    pub unit: Option<String>,
+    /// How the value is applied to the attribute, if given.
+    pub op: Option<StateUpdateOp>,
+}
+
+/// How the value in a [`StateUpdate`] is applied to the attribute.
+///
+/// When no operation is specified, the value should be treated as an override.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StateUpdateOp {
+    /// Replace the current value.
+    Override,
+    /// Add the value to the current value.
+    Add,
+    /// Subtract the value from the current value.
+    Sub,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
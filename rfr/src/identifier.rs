@@ -16,6 +16,8 @@ pub enum FormatVariant {
     RfrChunkedMeta,
     /// The chunked RFR callsites file. The string representation is `rfr-cc`.
     RfrChunkedCallsites,
+    /// The chunked RFR end file. The string representation is `rfr-ce`.
+    RfrChunkedEnd,
 }
 
 impl fmt::Display for FormatVariant {
@@ -31,6 +33,7 @@ impl FormatVariant {
             "rfr-c" => Some(Self::RfrChunked),
             "rfr-cm" => Some(Self::RfrChunkedMeta),
             "rfr-cc" => Some(Self::RfrChunkedCallsites),
+            "rfr-ce" => Some(Self::RfrChunkedEnd),
             _ => None,
         }
     }
@@ -41,6 +44,7 @@ impl FormatVariant {
             Self::RfrChunked => "rfr-c",
             Self::RfrChunkedMeta => "rfr-cm",
             Self::RfrChunkedCallsites => "rfr-cc",
+            Self::RfrChunkedEnd => "rfr-ce",
         }
     }
 }
@@ -0,0 +1,47 @@
+//! Types for reading recordings written with earlier versions of the formats.
+//!
+//! Only the parts of the formats which have changed are here, everything else is read with the
+//! current types.
+
+use serde::Deserialize;
+
+use crate::{AbsTimestamp, CallsiteId, InstrumentationId, Task, TaskId, TaskKind};
+
+/// A task as stored before the spawn location and the remaining spawn span fields were added
+/// (`rfr-c/0.0.2` and `rfr-s/0.0.2`).
+#[derive(Debug, Deserialize)]
+pub(crate) struct TaskWithoutLocation {
+    iid: InstrumentationId,
+    callsite_id: CallsiteId,
+    task_id: TaskId,
+    task_name: String,
+    task_kind: TaskKind,
+
+    context: Option<InstrumentationId>,
+}
+
+impl From<TaskWithoutLocation> for Task {
+    fn from(value: TaskWithoutLocation) -> Self {
+        Self {
+            iid: value.iid,
+            callsite_id: value.callsite_id,
+            task_id: value.task_id,
+            task_name: value.task_name,
+            task_kind: value.task_kind,
+            context: value.context,
+            location: None,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// Convert an absolute timestamp which was stored with microsecond precision.
+///
+/// The sub-second component used to be stored in microseconds, it is read into the nanoseconds
+/// field.
+pub(crate) fn abs_timestamp_from_micros(timestamp: AbsTimestamp) -> AbsTimestamp {
+    AbsTimestamp {
+        secs: timestamp.secs,
+        subsec_nanos: timestamp.subsec_nanos * 1_000,
+    }
+}
@@ -2,11 +2,13 @@ mod callsite;
 pub mod chunked;
 mod common;
 mod identifier;
+mod legacy;
 pub mod streamed;
 
 pub use callsite::{Callsite, CallsiteId};
 pub use common::{
-    AbsTimestamp, Event, Field, FieldName, FieldValue, InstrumentationId, Kind, Level, Parent,
-    Span, Task, TaskId, TaskKind, Waker,
+    AbsTimestamp, AsyncOp, Event, Field, FieldName, FieldValue, InstrumentationId, Kind, Level,
+    Location, Parent, PollOp, Resource, ResourceKind, Span, StateUpdate, StateUpdateOp, Task,
+    TaskId, TaskKind, Waker,
 };
 pub use identifier::{FormatIdentifier, FormatVariant, ParseFormatVersionError};
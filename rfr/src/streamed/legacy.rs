@@ -0,0 +1,114 @@
+//! Reading streaming recordings written with earlier versions of the format.
+//!
+//! The versions which can still be read are the variants of [`StreamFormat`]. Everything which
+//! is read is converted to the current types.
+
+use std::io;
+
+use serde::Deserialize;
+
+use crate::{
+    Callsite, Event, FormatIdentifier, FormatVariant, InstrumentationId, Span, Waker,
+    legacy::{TaskWithoutLocation, abs_timestamp_from_micros},
+    streamed::{Meta, Record, RecordData, current_software_version},
+};
+
+/// A version of the streaming format (`rfr-s`) which can be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamFormat {
+    /// `rfr-s/0.0.2`, timestamps have microsecond precision and tasks have no location or fields.
+    V0_0_2,
+    /// The current version.
+    Current,
+}
+
+impl StreamFormat {
+    /// Returns the stream format for the format identifier, if it can be read.
+    pub(crate) fn from_identifier(identifier: &FormatIdentifier) -> Option<Self> {
+        if current_software_version().can_read_version(identifier) {
+            return Some(Self::Current);
+        }
+
+        let v0_0_2 = FormatIdentifier {
+            variant: FormatVariant::RfrStreaming,
+            major: 0,
+            minor: 0,
+            patch: 2,
+        };
+        (identifier == &v0_0_2).then_some(Self::V0_0_2)
+    }
+
+    /// Deserialize a record in this format, converting it to the current types.
+    pub(crate) fn record_from_io<'a, R>(
+        self,
+        io: (R, &'a mut [u8]),
+    ) -> postcard::Result<(Record, (R, &'a mut [u8]))>
+    where
+        R: io::Read + 'a,
+    {
+        match self {
+            Self::Current => postcard::from_io(io),
+            Self::V0_0_2 => {
+                let (record, rest): (RecordV0_0_2, _) = postcard::from_io(io)?;
+                let meta = Meta {
+                    timestamp: abs_timestamp_from_micros(record.meta.timestamp),
+                };
+                Ok((Record::new(meta, record.data.into()), rest))
+            }
+        }
+    }
+}
+
+/// A record from `rfr-s/0.0.2`.
+#[derive(Debug, Deserialize)]
+struct RecordV0_0_2 {
+    meta: Meta,
+    data: RecordDataV0_0_2,
+}
+
+#[derive(Debug, Deserialize)]
+enum RecordDataV0_0_2 {
+    End,
+    Callsite { callsite: Callsite },
+    Span { span: Span },
+    Event { event: Event },
+    Task { task: TaskWithoutLocation },
+    SpanNew { iid: InstrumentationId },
+    SpanEnter { iid: InstrumentationId },
+    SpanExit { iid: InstrumentationId },
+    SpanClose { iid: InstrumentationId },
+    TaskNew { iid: InstrumentationId },
+    TaskPollStart { iid: InstrumentationId },
+    TaskPollEnd { iid: InstrumentationId },
+    TaskDrop { iid: InstrumentationId },
+    WakerWake { waker: Waker },
+    WakerWakeByRef { waker: Waker },
+    WakerClone { waker: Waker },
+    WakerDrop { waker: Waker },
+}
+
+impl From<RecordDataV0_0_2> for RecordData {
+    fn from(value: RecordDataV0_0_2) -> Self {
+        use RecordDataV0_0_2 as V0_0_2;
+
+        match value {
+            V0_0_2::End => Self::End,
+            V0_0_2::Callsite { callsite } => Self::Callsite { callsite },
+            V0_0_2::Span { span } => Self::Span { span },
+            V0_0_2::Event { event } => Self::Event { event },
+            V0_0_2::Task { task } => Self::Task { task: task.into() },
+            V0_0_2::SpanNew { iid } => Self::SpanNew { iid },
+            V0_0_2::SpanEnter { iid } => Self::SpanEnter { iid },
+            V0_0_2::SpanExit { iid } => Self::SpanExit { iid },
+            V0_0_2::SpanClose { iid } => Self::SpanClose { iid },
+            V0_0_2::TaskNew { iid } => Self::TaskNew { iid },
+            V0_0_2::TaskPollStart { iid } => Self::TaskPollStart { iid },
+            V0_0_2::TaskPollEnd { iid } => Self::TaskPollEnd { iid },
+            V0_0_2::TaskDrop { iid } => Self::TaskDrop { iid },
+            V0_0_2::WakerWake { waker } => Self::WakerWake { waker },
+            V0_0_2::WakerWakeByRef { waker } => Self::WakerWakeByRef { waker },
+            V0_0_2::WakerClone { waker } => Self::WakerClone { waker },
+            V0_0_2::WakerDrop { waker } => Self::WakerDrop { waker },
+        }
+    }
+}
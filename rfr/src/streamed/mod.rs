@@ -5,6 +5,7 @@ use crate::{
     Waker,
 };
 
+mod legacy;
 mod read;
 mod write;
 
@@ -16,7 +17,7 @@ fn current_software_version() -> FormatIdentifier {
         variant: FormatVariant::RfrStreaming,
         major: 0,
         minor: 0,
-        patch: 2,
+        patch: 3,
     }
 }
 
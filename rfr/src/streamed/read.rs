@@ -2,7 +2,7 @@ use std::{fs, io::SeekFrom};
 
 use crate::{
     FormatIdentifier,
-    streamed::{Record, current_software_version},
+    streamed::{Record, current_software_version, legacy::StreamFormat},
 };
 
 pub fn from_file(filename: String) -> Vec<Record> {
@@ -22,10 +22,11 @@ pub fn from_file(filename: String) -> Vec<Record> {
     };
 
     let (version, _): (FormatIdentifier, _) = postcard::from_io(file_buffer).unwrap();
-    let current = current_software_version();
-    if !current.can_read_version(&version) {
+    // Recordings written with an earlier version of the format are converted as they are read.
+    let Some(format) = StreamFormat::from_identifier(&version) else {
+        let current = current_software_version();
         panic!("Software version {current} cannot read file format version {version}",);
-    }
+    };
 
     let mut records = Vec::new();
 
@@ -57,7 +58,7 @@ pub fn from_file(filename: String) -> Vec<Record> {
                 continue;
             }
 
-            break match postcard::from_io(file_buffer) {
+            break match format.record_from_io(file_buffer) {
                 Ok(result) => result,
                 Err(postcard::Error::DeserializeUnexpectedEnd) => {
                     let new_size = buffer_vec.len() * 2;
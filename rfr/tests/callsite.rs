@@ -1,6 +1,6 @@
 use rfr::{
-    Callsite, CallsiteId, Kind, Level,
-    chunked::{ChunkedCallsites, ChunkedCallsitesWriter},
+    Callsite, CallsiteId, FieldName, Kind, Level,
+    chunked::{ChunkedCallsites, ChunkedCallsitesWriter, PushCallsiteResult},
 };
 
 #[test]
@@ -83,3 +83,82 @@ fn callsties_writer_new_fails() {
 
     assert!(new_result.is_err());
 }
+
+#[test]
+fn stable_callsite_id() {
+    let id = |line| {
+        CallsiteId::from_static_parts(
+            "runtime.spawn",
+            "tokio::task",
+            Some("tokio::task::spawn"),
+            Some("src/task/spawn.rs"),
+            Some(line),
+            ["kind", "task.name", "task.id"],
+        )
+    };
+
+    assert_eq!(id(168), id(168));
+    assert_ne!(id(168), id(169));
+
+    // Adjacent parts mustn't run into each other.
+    assert_ne!(
+        CallsiteId::from_static_parts("ab", "c", None, None, None, []),
+        CallsiteId::from_static_parts("a", "bc", None, None, None, []),
+    );
+    assert_ne!(
+        CallsiteId::from_static_parts("a", "b", Some("c"), None, None, []),
+        CallsiteId::from_static_parts("a", "b", None, Some("c"), None, []),
+    );
+}
+
+#[test]
+fn callsites_writer_detects_collision() {
+    let callsite = Callsite {
+        callsite_id: CallsiteId::from(1_u64),
+        level: Level(10),
+        kind: Kind::Event,
+        const_fields: vec![],
+        split_field_names: vec![],
+    };
+    let other_callsite = Callsite {
+        level: Level(20),
+        ..callsite.clone()
+    };
+    let mut buffer = Vec::new();
+    let mut writer = ChunkedCallsitesWriter::try_new(&mut buffer).unwrap();
+
+    assert!(matches!(
+        writer.push_callsite(callsite.clone()),
+        PushCallsiteResult::Added
+    ));
+    assert!(matches!(
+        writer.push_callsite(callsite),
+        PushCallsiteResult::Duplicate
+    ));
+    assert!(matches!(
+        writer.push_callsite(other_callsite),
+        PushCallsiteResult::Collision
+    ));
+    assert_eq!(1, writer.chunked_callsites().callsites.len());
+}
+
+#[test]
+fn truncated_callsites() {
+    let callsite = |id: u64| Callsite {
+        callsite_id: CallsiteId::from(id),
+        level: Level(10),
+        kind: Kind::Event,
+        const_fields: vec![],
+        split_field_names: vec![FieldName("message".into())],
+    };
+    let original = ChunkedCallsites::new(vec![callsite(1), callsite(2)]);
+    let mut buffer = Vec::new();
+    original.to_io(&mut buffer).unwrap();
+
+    // Cut the last callsite off part way through.
+    buffer.truncate(buffer.len() - 3);
+
+    let deser = ChunkedCallsites::try_from_io(buffer.as_slice()).unwrap();
+
+    assert_eq!(vec![callsite(1)], deser.callsites);
+}
@@ -1,5 +1,6 @@
 use rfr::{
-    AbsTimestamp, CallsiteId, InstrumentationId, Task, TaskKind,
+    AbsTimestamp, AsyncOp, CallsiteId, Field, FieldName, FieldValue, InstrumentationId, Location,
+    PollOp, Resource, ResourceKind, Task, TaskKind, Waker,
     chunked::{ChunkInterval, Meta, Object, Record, RecordData, SeqChunk, SeqChunkBuffer},
 };
 
@@ -19,10 +20,12 @@ fn round_trip() {
         },
         data: RecordData::TaskNew { iid: task.iid },
     };
-    seq_chunk_buffer.append_record(record.clone(), |_task_ids| {
-        vec![Some(Object::Task(task.clone()))]
-    });
-    seq_chunk_buffer.write(&mut buffer);
+    seq_chunk_buffer
+        .append_record(record.clone(), |_task_ids| {
+            vec![Some(Object::Task(task.clone()))]
+        })
+        .unwrap();
+    seq_chunk_buffer.write(&mut buffer).unwrap();
 
     assert!(!buffer.is_empty());
 
@@ -35,6 +38,8 @@ fn round_trip() {
         seq_chunk_buffer.earliest_timestamp()
     );
     assert_eq!(header.latest_timestamp, seq_chunk_buffer.latest_timestamp());
+    assert_eq!(header.thread, seq_chunk_buffer.thread());
+    assert_eq!(header.thread.name.as_deref(), std::thread::current().name());
 
     assert_eq!(seq_chunk.objects.len(), 1);
     assert_eq!(seq_chunk.objects[0], Object::Task(task));
@@ -44,23 +49,50 @@ fn round_trip() {
 }
 
 #[test]
-fn skip_records_with_unknown_objects() {
+fn keep_records_with_unknown_objects() {
+    let mut buffer = Vec::new();
+
     let seq_chunk_buffer = SeqChunkBuffer::new(ChunkInterval::from_timestamp_and_period(
         AbsTimestamp::now(),
         1_000_000,
     ));
 
+    let task = test_task(2);
+    let unknown_iid = InstrumentationId::from(5);
     let record = Record {
         meta: Meta {
             timestamp: seq_chunk_buffer.chunk_timestamp(&AbsTimestamp::now()),
         },
-        data: RecordData::TaskNew {
-            iid: InstrumentationId::from(5),
+        data: RecordData::WakerWake {
+            waker: Waker {
+                task_iid: unknown_iid,
+                context: Some(task.iid),
+            },
         },
     };
-    seq_chunk_buffer.append_record(record.clone(), |_task_ids| vec![None]);
+    seq_chunk_buffer
+        .append_record(record.clone(), |iids| {
+            assert_eq!(iids, &[unknown_iid, task.iid]);
+            vec![None, Some(Object::Task(task.clone()))]
+        })
+        .unwrap();
+    // The unresolved object isn't requested again.
+    seq_chunk_buffer
+        .append_record(record.clone(), |iids| {
+            assert!(iids.is_empty());
+            vec![]
+        })
+        .unwrap();
+
+    assert_eq!(seq_chunk_buffer.record_count(), 2);
+    assert_eq!(seq_chunk_buffer.unresolved_iids(), vec![unknown_iid]);
+
+    seq_chunk_buffer.write(&mut buffer).unwrap();
+    let seq_chunk: SeqChunk = postcard::from_bytes(buffer.as_mut_slice()).unwrap();
 
-    assert_eq!(seq_chunk_buffer.record_count(), 0);
+    assert_eq!(seq_chunk.objects, vec![Object::Task(task)]);
+    assert_eq!(seq_chunk.unresolved_iids, vec![unknown_iid]);
+    assert_eq!(seq_chunk.records, vec![record.clone(), record]);
 }
 
 #[test]
@@ -79,12 +111,14 @@ fn only_requests_object_once() {
         },
         data: RecordData::TaskNew { iid: task.iid },
     };
-    seq_chunk_buffer.append_record(record_1, |task_ids| {
-        assert_eq!(task_ids.len(), 1);
-        assert_eq!(task_ids[0], InstrumentationId::from(2));
+    seq_chunk_buffer
+        .append_record(record_1, |task_ids| {
+            assert_eq!(task_ids.len(), 1);
+            assert_eq!(task_ids[0], InstrumentationId::from(2));
 
-        vec![Some(Object::Task(task.clone()))]
-    });
+            vec![Some(Object::Task(task.clone()))]
+        })
+        .unwrap();
 
     let record_2 = Record {
         meta: Meta {
@@ -92,13 +126,71 @@ fn only_requests_object_once() {
         },
         data: RecordData::TaskDrop { iid: task.iid },
     };
-    seq_chunk_buffer.append_record(record_2, |task_ids| {
-        assert!(task_ids.is_empty());
+    seq_chunk_buffer
+        .append_record(record_2, |task_ids| {
+            assert!(task_ids.is_empty());
+
+            vec![]
+        })
+        .unwrap();
+
+    seq_chunk_buffer.write(&mut buffer).unwrap();
+}
 
-        vec![]
-    });
+#[test]
+fn poll_op_records_include_related_objects() {
+    let mut buffer = Vec::new();
+
+    let seq_chunk_buffer = SeqChunkBuffer::new(ChunkInterval::from_timestamp_and_period(
+        AbsTimestamp::now(),
+        1_000_000,
+    ));
+
+    let task = test_task(1);
+    let resource = Resource {
+        iid: InstrumentationId::from(2),
+        callsite_id: CallsiteId::from(6),
+        concrete_type: "Semaphore".into(),
+        kind: ResourceKind::Sync,
+        location: None,
+        is_internal: true,
+    };
+    let async_op = AsyncOp {
+        iid: InstrumentationId::from(3),
+        callsite_id: CallsiteId::from(7),
+        source: "Semaphore::acquire".into(),
+        resource_iid: resource.iid,
+        context: Some(task.iid),
+    };
+    let poll_op = PollOp {
+        resource_iid: resource.iid,
+        async_op_iid: Some(async_op.iid),
+        op_name: "poll_acquire".into(),
+        is_ready: false,
+        context: Some(task.iid),
+    };
+    let record = Record {
+        meta: Meta {
+            timestamp: seq_chunk_buffer.chunk_timestamp(&AbsTimestamp::now()),
+        },
+        data: RecordData::PollOp { poll_op },
+    };
+    seq_chunk_buffer
+        .append_record(record.clone(), |iids| {
+            assert_eq!(iids, &[resource.iid, async_op.iid, task.iid]);
+            vec![
+                Some(Object::Resource(resource.clone())),
+                Some(Object::AsyncOp(async_op.clone())),
+                Some(Object::Task(task.clone())),
+            ]
+        })
+        .unwrap();
+    seq_chunk_buffer.write(&mut buffer).unwrap();
+
+    let seq_chunk: SeqChunk = postcard::from_bytes(buffer.as_mut_slice()).unwrap();
 
-    seq_chunk_buffer.write(&mut buffer);
+    assert_eq!(seq_chunk.objects.len(), 3);
+    assert_eq!(seq_chunk.records, vec![record]);
 }
 
 fn test_task(iid: u64) -> Task {
@@ -109,5 +201,14 @@ fn test_task(iid: u64) -> Task {
         task_kind: TaskKind::Task,
         context: None,
         task_id: iid.into(),
+        location: Some(Location {
+            file: "src/main.rs".into(),
+            line: 12,
+            col: 5,
+        }),
+        fields: vec![Field {
+            name: FieldName("size.bytes".into()),
+            value: FieldValue::U64(1024),
+        }],
     }
 }
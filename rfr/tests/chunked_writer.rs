@@ -1,9 +1,17 @@
-use std::{fs, sync::Arc, thread, time::Duration};
+use std::{
+    fs,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use rfr::{
     AbsTimestamp, Callsite, CallsiteId, Event, FieldName, FieldValue, InstrumentationId, Kind,
     Level, Parent,
-    chunked::{self, ChunkedWriter, Meta, NewChunkedWriterError, Record, RecordData, from_path},
+    chunked::{
+        self, ChunkedEnd, ChunkedWriter, DirectoryLayout, DumpError, Meta, NewChunkedWriterError,
+        Record, RecordData, RecordingStatus, Retention, WriteChunksError, from_path,
+    },
 };
 use tempfile::tempdir;
 
@@ -33,6 +41,34 @@ fn no_objects(iids: &[InstrumentationId]) -> Vec<Option<chunked::Object>> {
     iids.iter().map(|_| None).collect()
 }
 
+/// Records a single event in each of the `count` whole seconds before `now_secs`.
+fn record_events_in_past_chunks(writer: &ChunkedWriter, now_secs: u64, count: u64) {
+    let callsite_id = CallsiteId::from(1);
+    for secs_ago in (1..=count).rev() {
+        let timestamp = AbsTimestamp {
+            secs: now_secs - secs_ago,
+            subsec_nanos: 0,
+        };
+        writer.with_seq_chunk_buffer(timestamp.clone(), |buffer| {
+            let record = Record {
+                meta: Meta {
+                    timestamp: buffer.chunk_timestamp(&timestamp),
+                },
+                data: RecordData::Event {
+                    event: Event {
+                        callsite_id,
+                        parent: Parent::Root,
+                        split_field_values: vec![],
+                        dynamic_fields: vec![],
+                    },
+                },
+            };
+
+            buffer.append_record(record, no_objects).unwrap();
+        });
+    }
+}
+
 #[test]
 fn record_single_event() {
     let recording_dir = tempdir().unwrap().path().join("recording.rfr");
@@ -50,7 +86,7 @@ fn record_single_event() {
         const_fields: vec![],
         split_field_names: vec![FieldName("message".into())],
     };
-    writer.register_callsite(callsite);
+    writer.register_callsite(callsite.clone());
 
     let event = Event {
         callsite_id,
@@ -68,7 +104,7 @@ fn record_single_event() {
             },
         };
 
-        buffer.append_record(record, no_objects);
+        buffer.append_record(record, no_objects).unwrap();
     });
 
     writer
@@ -77,6 +113,10 @@ fn record_single_event() {
     writer.close();
 
     let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    assert_eq!(recording.callsites().callsites, vec![callsite.clone()]);
+    assert_eq!(recording.callsite(&callsite_id), Some(&callsite));
+    assert_eq!(recording.callsite(&CallsiteId::from(2)), None);
+
     let chunks: Vec<_> = recording.chunks_lossy().flatten().collect();
 
     assert!(!chunks.is_empty());
@@ -98,6 +138,47 @@ fn record_single_event() {
     assert_eq!(actual_record.data, RecordData::Event { event });
 }
 
+#[test]
+fn colliding_callsite_ids_are_rehashed() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::try_new(&recording_dir).unwrap();
+
+    let callsite_id = CallsiteId::from(1);
+    let first = Callsite {
+        callsite_id,
+        level: Level(10),
+        kind: Kind::Event,
+        const_fields: vec![],
+        split_field_names: vec![FieldName("message".into())],
+    };
+    // A different callsite which has the same Id.
+    let second = Callsite {
+        kind: Kind::Span,
+        ..first.clone()
+    };
+
+    assert_eq!(writer.register_callsite(first.clone()), callsite_id);
+    let second_id = writer.register_callsite(second.clone());
+    assert_eq!(second_id, callsite_id.rehash());
+    // Registering either callsite again gives the same Id.
+    assert_eq!(writer.register_callsite(first.clone()), callsite_id);
+    assert_eq!(writer.register_callsite(second.clone()), second_id);
+
+    writer.finish().unwrap();
+
+    let recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    assert_eq!(recording.callsite(&callsite_id), Some(&first));
+    assert_eq!(
+        recording.callsite(&second_id),
+        Some(&Callsite {
+            callsite_id: second_id,
+            ..second
+        })
+    );
+    assert_eq!(recording.callsites().callsites.len(), 2);
+}
+
 #[test]
 fn directory_already_exists() {
     let recording_dir = tempdir().unwrap().path().join("recording.rfr");
@@ -140,3 +221,643 @@ fn meta_already_exists() {
         ),
     }
 }
+
+#[test]
+fn retention_removes_oldest_chunks() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let retention = Retention::default().with_max_duration(Duration::from_secs(2));
+    let writer = ChunkedWriter::builder()
+        .retention(retention)
+        .build(&recording_dir)
+        .unwrap();
+
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 5);
+    writer.write_all_chunks().unwrap();
+
+    assert!(recording_dir.join("meta.rfr").exists());
+    assert!(recording_dir.join("callsites.rfr").exists());
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let chunk_starts: Vec<_> = recording
+        .chunks_lossy()
+        .flatten()
+        .map(|chunk| chunk.header().interval.abs_start_time().secs)
+        .collect();
+    assert_eq!(chunk_starts, vec![now_secs - 2, now_secs - 1]);
+}
+
+#[test]
+fn dump_in_memory_ring() {
+    let base_dir = tempdir().unwrap();
+    let recording_dir = base_dir.path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .build_in_memory(Duration::from_secs(2))
+        .unwrap();
+    assert!(writer.is_in_memory());
+
+    // Leave a second for the chunks to be completed, so that they are all moved to the ring.
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs() - 1;
+    record_events_in_past_chunks(&writer, now_secs, 5);
+    writer.write_completed_chunks().unwrap();
+    assert!(
+        !recording_dir.exists(),
+        "nothing should be written before dumping"
+    );
+
+    writer.dump_to(&recording_dir).unwrap();
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let chunk_starts: Vec<_> = recording
+        .chunks_lossy()
+        .flatten()
+        .map(|chunk| chunk.header().interval.abs_start_time().secs)
+        .collect();
+    assert_eq!(chunk_starts, vec![now_secs - 2, now_secs - 1]);
+
+    match writer.dump_to(&recording_dir) {
+        Err(DumpError::AlreadyExists) => {} // expected result
+        other => panic!("expected error `DumpError::AlreadyExists`, but instead got `{other:?}`"),
+    }
+}
+
+#[test]
+fn invalid_chunk_period() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    for period in [
+        Duration::ZERO,
+        Duration::from_millis(300),
+        Duration::from_millis(1_500),
+        Duration::from_nanos(500),
+    ] {
+        match ChunkedWriter::builder()
+            .chunk_period(period)
+            .build(&recording_dir)
+        {
+            Err(NewChunkedWriterError::InvalidChunkPeriod(invalid)) => assert_eq!(invalid, period),
+            other => panic!(
+                "expected error `NewChunkedWriterError::InvalidChunkPeriod` for {period:?}, but instead got `{other:?}`"
+            ),
+        }
+    }
+    assert!(
+        !recording_dir.exists(),
+        "nothing should be written with an invalid chunk period"
+    );
+}
+
+#[test]
+fn sub_second_chunks_in_flat_layout() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .chunk_period(Duration::from_millis(250))
+        .directory_layout(DirectoryLayout::Flat)
+        .build(&recording_dir)
+        .unwrap();
+    assert_eq!(writer.chunk_period_micros(), 250_000);
+
+    let secs = AbsTimestamp::now().as_duration_since_epoch().as_secs() - 1;
+    let callsite_id = CallsiteId::from(1);
+    for subsec_nanos in [0, 250_000_000, 500_000_000, 750_000_000] {
+        let timestamp = AbsTimestamp { secs, subsec_nanos };
+        writer.with_seq_chunk_buffer(timestamp.clone(), |buffer| {
+            let record = Record {
+                meta: Meta {
+                    timestamp: buffer.chunk_timestamp(&timestamp),
+                },
+                data: RecordData::Event {
+                    event: Event {
+                        callsite_id,
+                        parent: Parent::Root,
+                        split_field_values: vec![],
+                        dynamic_fields: vec![],
+                    },
+                },
+            };
+
+            buffer.append_record(record, no_objects).unwrap();
+        });
+    }
+    writer.write_all_chunks().unwrap();
+
+    let mut chunk_files: Vec<_> = fs::read_dir(&recording_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("chunk-"))
+        .collect();
+    chunk_files.sort();
+    assert_eq!(chunk_files.len(), 4, "each chunk should have its own file");
+    assert!(chunk_files[1].ends_with(".250000.rfr"));
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let chunk_starts: Vec<_> = recording
+        .chunks_lossy()
+        .flatten()
+        .map(|chunk| chunk.header().interval.abs_start_time())
+        .collect();
+    assert_eq!(
+        chunk_starts,
+        [0, 250_000_000, 500_000_000, 750_000_000]
+            .map(|subsec_nanos| AbsTimestamp { secs, subsec_nanos })
+            .to_vec()
+    );
+}
+
+#[test]
+fn finish_writes_remaining_chunks() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::try_new(&recording_dir).unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    // These chunks are complete, but there is no writer loop to write them.
+    record_events_in_past_chunks(&writer, now_secs, 2);
+    assert!(!recording_dir.join("end.rfr").exists());
+
+    writer.finish().unwrap();
+    assert!(writer.is_closed());
+
+    let end_file = fs::File::open(recording_dir.join("end.rfr")).unwrap();
+    let end = ChunkedEnd::try_from_io(end_file).unwrap();
+    assert!(end.header.end_time.secs >= now_secs);
+    assert_eq!(end.header.stats.records_written.get("Event"), Some(&2));
+    assert_eq!(end.header.stats.chunks_written, 2);
+    assert!(end.header.clean);
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let chunk_starts: Vec<_> = recording
+        .chunks_lossy()
+        .flatten()
+        .map(|chunk| chunk.header().interval.abs_start_time().secs)
+        .collect();
+    assert_eq!(chunk_starts, vec![now_secs - 2, now_secs - 1]);
+
+    // Finishing again does nothing.
+    writer.finish().unwrap();
+}
+
+#[test]
+fn builder_metadata_is_written() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let _writer = ChunkedWriter::builder()
+        .tokio_version("1.45.0")
+        .worker_threads(4)
+        .label("service", "checkout")
+        .build(&recording_dir)
+        .unwrap();
+
+    let recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let header = &recording.meta().header;
+    assert_eq!(header.clock_anchor, AbsTimestamp::clock_anchor());
+    assert!(header.clock_anchor <= header.created_time);
+    assert_eq!(header.process.pid, std::process::id());
+    assert_eq!(header.runtime.tokio_version.as_deref(), Some("1.45.0"));
+    assert_eq!(header.runtime.worker_threads, Some(4));
+    assert_eq!(
+        header.labels.get("service").map(String::as_str),
+        Some("checkout")
+    );
+}
+
+#[test]
+fn recording_status() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+    let read_recording = || from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+
+    let writer = ChunkedWriter::try_new(&recording_dir).unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 2);
+    writer.write_completed_chunks().unwrap();
+
+    // Without an end file, the recording looks like it ended abruptly at the last record.
+    let mut recording = read_recording();
+    assert!(recording.end().is_none());
+    assert_eq!(
+        recording.status(),
+        RecordingStatus::Unfinished {
+            latest_timestamp: Some(AbsTimestamp {
+                secs: now_secs - 1,
+                subsec_nanos: 0,
+            }),
+        }
+    );
+
+    writer.finish().unwrap();
+    let mut recording = read_recording();
+    let end_time = recording.end().unwrap().header.end_time.clone();
+    assert_eq!(recording.status(), RecordingStatus::Finished { end_time });
+}
+
+#[test]
+fn unreadable_end_file_is_unfinished() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::try_new(&recording_dir).unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 2);
+    writer.finish().unwrap();
+
+    // Truncate the end file, as if the process had crashed while writing it.
+    let end_path = recording_dir.join("end.rfr");
+    let end_bytes = fs::read(&end_path).unwrap();
+    fs::write(&end_path, &end_bytes[..end_bytes.len() / 2]).unwrap();
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    assert!(recording.end().is_none());
+    assert_eq!(
+        recording.status(),
+        RecordingStatus::Unfinished {
+            latest_timestamp: Some(AbsTimestamp {
+                secs: now_secs - 1,
+                subsec_nanos: 0,
+            }),
+        }
+    );
+}
+
+#[test]
+fn close_wakes_sleeping_writer() {
+    let writer = Arc::new(
+        ChunkedWriter::builder()
+            .build_in_memory(Duration::from_secs(1))
+            .unwrap(),
+    );
+
+    let sleeping_writer = Arc::clone(&writer);
+    let sleeper = thread::spawn(move || {
+        let start = Instant::now();
+        sleeping_writer.sleep_unless_closed(Duration::from_secs(60));
+        start.elapsed()
+    });
+
+    thread::sleep(Duration::from_millis(50));
+    writer.close();
+
+    let slept = sleeper.join().unwrap();
+    assert!(
+        slept < Duration::from_secs(10),
+        "sleep should end when the writer is closed, but slept for {slept:?}"
+    );
+
+    // Once closed, sleeping returns immediately.
+    let start = Instant::now();
+    writer.sleep_unless_closed(Duration::from_secs(60));
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn write_errors_are_returned() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::try_new(&recording_dir).unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 2);
+
+    // Replace the recording directory with a file, so that no chunks can be written.
+    fs::remove_dir_all(&recording_dir).unwrap();
+    fs::write(&recording_dir, b"").unwrap();
+
+    match writer.write_completed_chunks() {
+        Err(WriteChunksError::CreateDirFailed(_)) => {} // expected result
+        other => panic!(
+            "expected error `WriteChunksError::CreateDirFailed`, but instead got `{other:?}`"
+        ),
+    }
+    assert_eq!(writer.stats().write_errors, 2);
+
+    // The chunks which failed to be written are discarded.
+    writer.write_completed_chunks().unwrap();
+    assert_eq!(writer.stats().write_errors, 2);
+}
+
+#[test]
+fn writer_stats() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::try_new(&recording_dir).unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 3);
+    assert_eq!(writer.stats().total_records_written(), 3);
+    assert_eq!(writer.stats().chunks_written, 0);
+
+    writer.write_completed_chunks().unwrap();
+    let stats = writer.stats();
+    assert_eq!(stats.chunks_written, 3);
+    assert!(stats.chunk_bytes_max > 0);
+    assert!(stats.chunk_bytes_total >= stats.chunk_bytes_max);
+    assert_eq!(stats.late_records, 0);
+
+    // This chunk has already been written.
+    record_events_in_past_chunks(&writer, now_secs, 1);
+    let stats = writer.stats();
+    assert_eq!(stats.late_records, 1);
+    assert_eq!(stats.records_written.get("Event"), Some(&4));
+}
+
+#[test]
+fn late_records_are_merged_into_written_chunk() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .build(&recording_dir)
+        .unwrap();
+    // Leave a second for the chunk to be completed, so that it is written straight away.
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs() - 1;
+    record_events_in_past_chunks(&writer, now_secs, 1);
+    writer.write_completed_chunks().unwrap();
+
+    // This chunk has already been written and discarded.
+    record_events_in_past_chunks(&writer, now_secs, 1);
+    writer.write_completed_chunks().unwrap();
+
+    let file_names: Vec<_> = fs::read_dir(&recording_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("chunk-"))
+        .collect();
+    assert_eq!(
+        file_names.len(),
+        1,
+        "temporary files should be renamed: {file_names:?}"
+    );
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let record_counts: Vec<usize> = recording
+        .chunks_lossy()
+        .flatten()
+        .map(|chunk| {
+            chunk
+                .seq_chunks()
+                .iter()
+                .map(|seq_chunk| seq_chunk.records.len())
+                .sum()
+        })
+        .collect();
+    assert_eq!(record_counts, vec![2]);
+}
+
+#[test]
+fn late_records_are_written_separately_if_chunk_is_unreadable() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .build(&recording_dir)
+        .unwrap();
+    // Leave a second for the chunk to be completed, so that it is written straight away.
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs() - 1;
+    record_events_in_past_chunks(&writer, now_secs, 1);
+    writer.write_completed_chunks().unwrap();
+
+    let chunk_files = || -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&recording_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("chunk-"))
+            .collect();
+        names.sort();
+        names
+    };
+    let written_files = chunk_files();
+    assert_eq!(written_files.len(), 1);
+
+    // Truncate the written chunk, so that it can't be read to merge the late record into.
+    let chunk_path = recording_dir.join(&written_files[0]);
+    let chunk_bytes = fs::read(&chunk_path).unwrap();
+    fs::write(&chunk_path, &chunk_bytes[..4]).unwrap();
+
+    record_events_in_past_chunks(&writer, now_secs, 1);
+    writer.write_completed_chunks().unwrap();
+
+    let files = chunk_files();
+    assert_eq!(files.len(), 2, "unexpected chunk files: {files:?}");
+    assert!(files.contains(&written_files[0]));
+    assert!(files.iter().any(|name| name.ends_with(".late-1.rfr")));
+    assert_eq!(fs::read(&chunk_path).unwrap(), &chunk_bytes[..4]);
+
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let record_counts: Vec<usize> = recording
+        .chunks_lossy()
+        .flatten()
+        .map(|chunk| {
+            chunk
+                .seq_chunks()
+                .iter()
+                .map(|seq_chunk| seq_chunk.records.len())
+                .sum()
+        })
+        .collect();
+    assert_eq!(record_counts, vec![1]);
+}
+
+#[test]
+fn checkpoints_in_progress_chunks() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .chunk_period(Duration::from_secs(60))
+        .checkpoint_interval(Duration::from_millis(100))
+        .build(&recording_dir)
+        .unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    // A chunk which is still being recorded to (unless the test runs right at the end of a minute).
+    record_events_in_past_chunks(&writer, now_secs + 1, 1);
+
+    let sleep_duration = writer.write_completed_chunks().unwrap();
+    assert!(sleep_duration <= Duration::from_millis(100));
+
+    let chunk_files = || -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&recording_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("chunk-"))
+            .collect();
+        names.sort();
+        names
+    };
+    let partial_files = chunk_files();
+    assert_eq!(partial_files.len(), 1);
+    assert!(partial_files[0].ends_with(".partial.rfr"));
+
+    // The partial chunk is readable as part of the recording.
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    assert_eq!(recording.chunks_lossy().flatten().count(), 1);
+
+    // Writing the complete chunk removes the partial chunk.
+    writer.write_all_chunks().unwrap();
+    let files = chunk_files();
+    assert_eq!(files.len(), 1);
+    assert!(!files[0].ends_with(".partial.rfr"));
+}
+
+#[test]
+fn checkpoints_replace_written_chunks() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .chunk_period(Duration::from_secs(60))
+        .checkpoint_interval(Duration::from_millis(50))
+        .build(&recording_dir)
+        .unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    // The chunk is written while it is still being recorded to (unless the test runs right at the
+    // end of a minute).
+    record_events_in_past_chunks(&writer, now_secs + 1, 1);
+    writer.write_all_chunks().unwrap();
+
+    // The next record is only checkpointed, a partial chunk would be ignored next to the complete
+    // chunk which has already been written.
+    record_events_in_past_chunks(&writer, now_secs + 1, 1);
+    thread::sleep(Duration::from_millis(60));
+    writer.write_completed_chunks().unwrap();
+
+    let file_names: Vec<_> = fs::read_dir(&recording_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("chunk-"))
+        .collect();
+    assert_eq!(
+        file_names.len(),
+        1,
+        "unexpected chunk files: {file_names:?}"
+    );
+    assert!(!file_names[0].ends_with(".partial.rfr"));
+
+    // Read the recording without finishing it, as if the process had crashed.
+    let mut recording = from_path(recording_dir.to_str().unwrap().to_owned()).unwrap();
+    let record_count: usize = recording
+        .chunks_lossy()
+        .flatten()
+        .flat_map(|chunk| chunk.seq_chunks())
+        .map(|seq_chunk| seq_chunk.records.len())
+        .sum();
+    assert_eq!(record_count, 2);
+}
+
+#[test]
+fn failed_checkpoints_are_counted_separately() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    // With a long write delay, the chunk is only ever checkpointed.
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .write_delay(Duration::from_secs(3600))
+        .checkpoint_interval(Duration::from_millis(1))
+        .build(&recording_dir)
+        .unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 1);
+
+    // Replace the recording directory with a file, so that no checkpoints can be written.
+    fs::remove_dir_all(&recording_dir).unwrap();
+    fs::write(&recording_dir, b"").unwrap();
+
+    match writer.write_completed_chunks() {
+        Err(WriteChunksError::CreateDirFailed(_)) => {} // expected result
+        other => panic!(
+            "expected error `WriteChunksError::CreateDirFailed`, but instead got `{other:?}`"
+        ),
+    }
+    let stats = writer.stats();
+    assert_eq!(stats.checkpoint_errors, 1);
+    assert_eq!(stats.write_errors, 0);
+}
+
+#[test]
+fn retention_removes_partial_chunks() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    // With a long write delay, the chunks are only checkpointed by `write_completed_chunks`.
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .write_delay(Duration::from_secs(3600))
+        .checkpoint_interval(Duration::from_millis(1))
+        .retention(Retention::default().with_max_duration(Duration::from_secs(2)))
+        .build(&recording_dir)
+        .unwrap();
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs();
+    record_events_in_past_chunks(&writer, now_secs, 5);
+
+    let chunk_files = || -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&recording_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("chunk-"))
+            .collect();
+        names.sort();
+        names
+    };
+    writer.write_completed_chunks().unwrap();
+    assert_eq!(chunk_files().len(), 5);
+
+    // Retention is applied to the partial chunks on the next run.
+    writer.write_completed_chunks().unwrap();
+    let partial_files = chunk_files();
+    assert_eq!(
+        partial_files.len(),
+        2,
+        "unexpected chunk files: {partial_files:?}"
+    );
+    assert!(
+        partial_files
+            .iter()
+            .all(|name| name.ends_with(".partial.rfr"))
+    );
+
+    // The complete chunks supersede the partial chunks, which are removed.
+    writer.write_all_chunks().unwrap();
+    let files = chunk_files();
+    assert_eq!(files.len(), 2, "unexpected chunk files: {files:?}");
+    assert!(files.iter().all(|name| !name.ends_with(".partial.rfr")));
+}
+
+#[test]
+fn retention_removes_late_chunks() {
+    let recording_dir = tempdir().unwrap().path().join("recording.rfr");
+
+    let writer = ChunkedWriter::builder()
+        .directory_layout(DirectoryLayout::Flat)
+        .retention(Retention::default().with_max_duration(Duration::from_secs(2)))
+        .build(&recording_dir)
+        .unwrap();
+    // Leave a second for the chunks to be completed, so that they are written straight away.
+    let now_secs = AbsTimestamp::now().as_duration_since_epoch().as_secs() - 1;
+    record_events_in_past_chunks(&writer, now_secs - 3, 1);
+    writer.write_completed_chunks().unwrap();
+
+    let chunk_files = || -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&recording_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("chunk-"))
+            .collect();
+        names.sort();
+        names
+    };
+
+    // Truncate the written chunk, so that the late record is written to a separate chunk.
+    let written_files = chunk_files();
+    assert_eq!(written_files.len(), 1);
+    fs::write(recording_dir.join(&written_files[0]), b"rfr").unwrap();
+    record_events_in_past_chunks(&writer, now_secs - 3, 1);
+    writer.write_completed_chunks().unwrap();
+    let files = chunk_files();
+    assert!(
+        files.iter().any(|name| name.ends_with(".late-1.rfr")),
+        "unexpected chunk files: {files:?}"
+    );
+
+    // Both files for the oldest chunk are removed together.
+    record_events_in_past_chunks(&writer, now_secs, 3);
+    writer.write_completed_chunks().unwrap();
+    let files = chunk_files();
+    assert_eq!(files.len(), 2, "unexpected chunk files: {files:?}");
+    assert!(files.iter().all(|name| !name.ends_with(".late-1.rfr")));
+}
@@ -0,0 +1,164 @@
+//! Recordings written with earlier versions of the formats, which can still be read.
+
+use std::path::PathBuf;
+
+use rfr::{
+    AbsTimestamp, CallsiteId, Event, FieldValue, InstrumentationId, Parent, Task, TaskId, TaskKind,
+    Waker,
+    chunked::{self, ProcessInfo, RecordData, RecordingStatus, from_path},
+    streamed,
+};
+
+fn recording_path(name: &str) -> String {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/recordings")
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn at_micros(subsec_micros: u32) -> AbsTimestamp {
+    AbsTimestamp {
+        secs: 1_700_000_000,
+        subsec_nanos: subsec_micros * 1_000,
+    }
+}
+
+fn expected_task() -> Task {
+    Task {
+        iid: InstrumentationId::from(1),
+        callsite_id: CallsiteId::from(1),
+        task_id: TaskId::from(7),
+        task_name: "worker".to_owned(),
+        task_kind: TaskKind::Task,
+        context: None,
+        location: None,
+        fields: Vec::new(),
+    }
+}
+
+fn expected_event() -> Event {
+    Event {
+        callsite_id: CallsiteId::from(2),
+        parent: Parent::Current,
+        split_field_values: vec![FieldValue::Str("polled".to_owned())],
+        dynamic_fields: Vec::new(),
+    }
+}
+
+#[test]
+fn read_chunked_with_micros_timestamps() {
+    // Written with `rfr-c/0.0.2`, `rfr-cm/0.0.1`, and `rfr-cc/0.0.1`. There was no end file.
+    let mut recording = from_path(recording_path("chunked-0.0.2.rfr")).unwrap();
+
+    let meta = recording.meta();
+    assert_eq!(meta.format_identifier.to_string(), "rfr-cm/0.0.1");
+    let header = &meta.header;
+    assert_eq!(header.format_identifiers[0].to_string(), "rfr-c/0.0.2");
+    assert_eq!(header.created_time.subsec_nanos % 1_000, 0);
+    assert_eq!(header.clock_anchor, header.created_time);
+    assert_eq!(header.process, ProcessInfo::default());
+    assert_eq!(recording.callsites().callsites.len(), 2);
+    assert_eq!(
+        recording.status(),
+        RecordingStatus::Unfinished {
+            latest_timestamp: Some(at_micros(900_000)),
+        }
+    );
+
+    let iid = InstrumentationId::from(1);
+    let chunks: Vec<_> = recording.chunks_lossy().flatten().collect();
+    assert_eq!(chunks.len(), 1);
+    let chunk = chunks[0];
+    assert_eq!(chunk.header().interval.abs_start_time(), at_micros(0));
+    assert_eq!(
+        chunk.header().interval.abs_end_time(),
+        AbsTimestamp {
+            secs: 1_700_000_001,
+            subsec_nanos: 0,
+        }
+    );
+    assert_eq!(
+        chunk.abs_timestamp(&chunk.header().earliest_timestamp),
+        at_micros(250_000)
+    );
+    assert_eq!(
+        chunk.abs_timestamp(&chunk.header().latest_timestamp),
+        at_micros(900_000)
+    );
+    assert!(chunk.header().clock_adjustments.is_empty());
+
+    let seq_chunk = &chunk.seq_chunks()[0];
+    assert_eq!(seq_chunk.header.thread.name, None);
+    assert_eq!(seq_chunk.header.thread.first_seen, at_micros(250_000));
+    assert_eq!(
+        seq_chunk.objects,
+        vec![chunked::Object::Task(expected_task())]
+    );
+    assert!(seq_chunk.unresolved_iids.is_empty());
+    let records: Vec<_> = seq_chunk
+        .records
+        .iter()
+        .map(|record| (chunk.abs_timestamp(&record.meta.timestamp), &record.data))
+        .collect();
+    assert_eq!(
+        records,
+        vec![
+            (at_micros(250_000), &RecordData::TaskNew { iid }),
+            (at_micros(250_500), &RecordData::TaskPollStart { iid }),
+            (
+                at_micros(251_000),
+                &RecordData::Event {
+                    event: expected_event()
+                }
+            ),
+            (at_micros(251_500), &RecordData::TaskPollEnd { iid }),
+            (
+                at_micros(600_000),
+                &RecordData::WakerWake {
+                    waker: Waker {
+                        task_iid: iid,
+                        context: None,
+                    },
+                },
+            ),
+            (at_micros(900_000), &RecordData::TaskDrop { iid }),
+        ]
+    );
+}
+
+#[test]
+fn read_streamed_with_micros_timestamps() {
+    // Written with `rfr-s/0.0.2`.
+    let records = streamed::from_file(recording_path("streamed-0.0.2.rfr"));
+
+    let timestamps: Vec<_> = records
+        .iter()
+        .map(|record| record.meta.timestamp.clone())
+        .collect();
+    assert_eq!(
+        timestamps,
+        vec![
+            at_micros(250_000),
+            at_micros(250_000),
+            at_micros(250_000),
+            at_micros(250_000),
+            at_micros(250_500),
+            at_micros(251_000),
+            at_micros(251_500),
+            at_micros(600_000),
+            at_micros(900_000),
+            at_micros(900_000),
+        ]
+    );
+    match &records[2].data {
+        streamed::RecordData::Task { task } => assert_eq!(task, &expected_task()),
+        other => panic!("expected a task, but got `{other:?}`"),
+    }
+    match &records[5].data {
+        streamed::RecordData::Event { event } => assert_eq!(event, &expected_event()),
+        other => panic!("expected an event, but got `{other:?}`"),
+    }
+    assert!(matches!(records[9].data, streamed::RecordData::End));
+}
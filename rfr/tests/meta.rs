@@ -1,7 +1,9 @@
 // TODO(hds): Write tests for meta file handling
+use std::collections::BTreeMap;
+
 use rfr::{
     AbsTimestamp, FormatIdentifier, FormatVariant,
-    chunked::{ChunkedMeta, ChunkedMetaHeader, MetaTryFromIoError},
+    chunked::{ChunkedMeta, ChunkedMetaHeader, MetaTryFromIoError, ProcessInfo, RuntimeInfo},
 };
 
 #[test]
@@ -22,6 +24,31 @@ fn round_trip_meta() {
     assert_eq!(meta.header.format_identifiers, vec![chunked_identifier]);
 }
 
+#[test]
+fn new_meta_describes_current_process() {
+    let chunked_identifier = FormatIdentifier {
+        variant: FormatVariant::RfrChunked,
+        major: 0,
+        minor: 0,
+        patch: 1,
+    };
+    let mut meta = ChunkedMeta::new(vec![chunked_identifier]);
+    meta.header
+        .labels
+        .insert("service".to_owned(), "checkout".to_owned());
+
+    assert_eq!(meta.header.process.pid, std::process::id());
+    assert!(!meta.header.process.args.is_empty());
+    assert_eq!(meta.header.runtime.rfr_version, env!("CARGO_PKG_VERSION"));
+    assert!(meta.header.runtime.rust_version.is_some());
+
+    let buffer = postcard::to_stdvec(&meta).unwrap();
+    let read_meta = ChunkedMeta::try_from_io(buffer.as_slice()).unwrap();
+    assert_eq!(read_meta.header.process, meta.header.process);
+    assert_eq!(read_meta.header.runtime, meta.header.runtime);
+    assert_eq!(read_meta.header.labels, meta.header.labels);
+}
+
 // TODO(hds): also test with a method on ChunkedMeta that correctly checks the version in the meta
 // file. Then check that we correctly identify when the version can't be read.
 
@@ -74,7 +101,11 @@ fn try_from_io_incompatible_format() {
         },
         header: ChunkedMetaHeader {
             created_time: AbsTimestamp::now(),
+            clock_anchor: AbsTimestamp::clock_anchor(),
             format_identifiers: vec![chunked_identifier],
+            process: ProcessInfo::default(),
+            runtime: RuntimeInfo::default(),
+            labels: BTreeMap::new(),
         },
     };
 
@@ -97,7 +128,7 @@ fn try_from_io_file_invalid() {
         variant: FormatVariant::RfrChunkedMeta,
         major: 0,
         minor: 0,
-        patch: 1,
+        patch: 2,
     };
 
     let mut buffer = postcard::to_stdvec(&format_identifier).unwrap();
@@ -115,11 +146,15 @@ fn try_from_io_missing_format_identifiers() {
             variant: FormatVariant::RfrChunkedMeta,
             major: 0,
             minor: 0,
-            patch: 1,
+            patch: 2,
         },
         header: ChunkedMetaHeader {
             created_time: AbsTimestamp::now(),
+            clock_anchor: AbsTimestamp::clock_anchor(),
             format_identifiers: vec![],
+            process: ProcessInfo::default(),
+            runtime: RuntimeInfo::default(),
+            labels: BTreeMap::new(),
         },
     };
 